@@ -0,0 +1,43 @@
+#[macro_use]
+extern crate criterion;
+extern crate mmpp;
+
+use criterion::{BatchSize, Criterion, Throughput};
+
+fn inputs() -> Vec<(&'static str, String)> {
+    let leaf = "host(22CXRB3pZmu, loadavg5)";
+    let group = format!("group({})", vec![leaf; 1000].join(", "));
+    let mut chain = leaf.to_string();
+    for i in 0..mmpp::MAX_DEPTH - 1 {
+        chain = if i % 2 == 0 { format!("scale({}, 1/1024)", chain) } else { format!("offset({}, -1.5e3)", chain) };
+    }
+    let alias = format!("alias({}, '{}')", leaf, "load average (5 min) of db01, ".repeat(1000));
+    vec![("leaf", leaf.to_string()), ("wide group", group), ("deep chain", chain), ("long alias", alias)]
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_metric");
+    for (name, src) in inputs() {
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_function(name, |b| b.iter(|| mmpp::parse_metric(&src).unwrap()));
+    }
+    group.finish();
+}
+
+fn bench_print(c: &mut Criterion) {
+    for &(printer_name, printer) in &[
+        ("pretty_print", mmpp::pretty_print as fn(mmpp::Metric) -> String),
+        ("compact_print", mmpp::compact_print),
+    ] {
+        let mut group = c.benchmark_group(printer_name);
+        for (name, src) in inputs() {
+            let metric = mmpp::parse_metric(&src).unwrap();
+            group.throughput(Throughput::Bytes(printer(metric.clone()).len() as u64));
+            group.bench_function(name, |b| b.iter_batched(|| metric.clone(), printer, BatchSize::SmallInput));
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_parse, bench_print);
+criterion_main!(benches);
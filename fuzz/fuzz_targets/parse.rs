@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(src) = std::str::from_utf8(data) {
+        let _ = mmpp::parse_metric(src);
+        let _ = mmpp::parse_metric_spanned(src);
+    }
+});
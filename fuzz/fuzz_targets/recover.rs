@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mmpp::recover::PartialMetric;
+use mmpp::Span;
+
+fn check_span(src: &str, span: Span) {
+    assert!(span.start <= span.end && span.end <= src.len(), "{:?}", span);
+    assert!(src.is_char_boundary(span.start) && src.is_char_boundary(span.end), "{:?}", span);
+}
+
+fn check_metric(src: &str, metric: &PartialMetric) {
+    match *metric {
+        PartialMetric::Metric(_, span) | PartialMetric::Argument(_, span) | PartialMetric::Error(span) => {
+            check_span(src, span)
+        }
+        PartialMetric::Call(_, ref children, span) => {
+            check_span(src, span);
+            children.iter().for_each(|child| check_metric(src, child));
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(src) = std::str::from_utf8(data) else { return };
+    let recovery = mmpp::recover::parse_recovering(src);
+    check_metric(src, &recovery.metric);
+    for err in recovery.errors.iter().chain(recovery.warnings.iter()) {
+        check_span(src, err.span);
+    }
+});
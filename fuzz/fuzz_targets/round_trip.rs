@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(src) = std::str::from_utf8(data) else { return };
+    let Ok(metric) = mmpp::parse_metric(src) else { return };
+    let printed = mmpp::pretty_print(metric.clone());
+    assert_eq!(mmpp::parse_metric(&printed), Ok(metric.clone()), "{}", printed);
+    let printed = mmpp::compact_print(metric.clone());
+    assert_eq!(mmpp::parse_metric(&printed), Ok(metric), "{}", printed);
+});
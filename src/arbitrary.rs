@@ -0,0 +1,137 @@
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use recover::{matches, parse_recovering, PartialMetric};
+use {compact_print, parse_metric, pretty_print, Argument, Duration, Factor, Metric, Percentage, Rule, Span};
+use functions;
+
+const DOUBLE: &str = "-?([0-9]{1,4}(\\.[0-9]{0,3})?|[0-9]{0,3}\\.[0-9]{1,3})([eE][+-]?[0-9]{1,2})?";
+
+fn host_id() -> BoxedStrategy<String> {
+    "[a-zA-Z0-9]{1,12}".boxed()
+}
+
+fn name() -> BoxedStrategy<String> {
+    "[a-zA-Z0-9_-]{1,10}".boxed()
+}
+
+fn metric_name() -> BoxedStrategy<String> {
+    "[a-zA-Z0-9_.*-]{1,16}".boxed()
+}
+
+fn factor() -> BoxedStrategy<Factor> {
+    prop_oneof![
+        DOUBLE.prop_map(Factor::Double),
+        (DOUBLE, DOUBLE).prop_map(|(nume, deno)| Factor::Fraction(nume, deno)),
+    ].boxed()
+}
+
+fn percentage() -> BoxedStrategy<Percentage> {
+    DOUBLE.prop_map(Percentage).boxed()
+}
+
+fn duration() -> BoxedStrategy<Duration> {
+    "[0-9]{1,3}(m|mo|h|d|w|y)".prop_map(Duration).boxed()
+}
+
+fn display_name() -> BoxedStrategy<String> {
+    "[a-zA-Z0-9 \t\n\r'\"(),.:_あ-ん-]{0,12}"
+        .prop_filter("cannot quote both quotes", |name| !(name.contains('\'') && name.contains('"')))
+        .boxed()
+}
+
+fn function_name() -> BoxedStrategy<String> {
+    "[a-zA-Z][a-zA-Z0-9_]{0,12}"
+        .prop_filter("must not be a known function", |name| functions::function(name).is_none())
+        .boxed()
+}
+
+fn argument(metric: BoxedStrategy<Metric>) -> BoxedStrategy<Argument> {
+    prop_oneof![
+        metric.prop_map(Argument::Metric),
+        factor().prop_map(Argument::Factor),
+        duration().prop_map(Argument::Duration),
+        display_name()
+            .prop_filter("must not be a duration", |s| !matches(Rule::duration, s))
+            .prop_map(Argument::String),
+    ].boxed()
+}
+
+pub fn metric() -> BoxedStrategy<Metric> {
+    let leaf = prop_oneof![
+        (host_id(), metric_name()).prop_map(|(host_id, metric_name)| Metric::Host(host_id, metric_name)),
+        (name(), metric_name()).prop_map(|(service_name, metric_name)| Metric::Service(service_name, metric_name)),
+        (name(), name(), metric_name()).prop_map(|(service_name, role_name, metric_name)| {
+            Metric::Role(service_name, role_name, metric_name)
+        }),
+        (name(), name(), metric_name()).prop_map(|(service_name, role_name, metric_name)| {
+            Metric::RoleSlot(service_name, role_name, metric_name)
+        }),
+    ];
+    leaf.prop_recursive(5, 48, 4, |inner| {
+        prop_oneof![
+            inner.clone().prop_map(|metric| Metric::Avg(Box::new(metric))),
+            inner.clone().prop_map(|metric| Metric::Max(Box::new(metric))),
+            inner.clone().prop_map(|metric| Metric::Min(Box::new(metric))),
+            inner.clone().prop_map(|metric| Metric::Sum(Box::new(metric))),
+            inner.clone().prop_map(|metric| Metric::Product(Box::new(metric))),
+            (inner.clone(), inner.clone()).prop_map(|(metric1, metric2)| {
+                Metric::Diff(Box::new(metric1), Box::new(metric2))
+            }),
+            (inner.clone(), inner.clone()).prop_map(|(metric1, metric2)| {
+                Metric::Divide(Box::new(metric1), Box::new(metric2))
+            }),
+            (inner.clone(), factor()).prop_map(|(metric, factor)| Metric::Scale(Box::new(metric), factor)),
+            (inner.clone(), factor()).prop_map(|(metric, factor)| Metric::Offset(Box::new(metric), factor)),
+            (inner.clone(), percentage()).prop_map(|(metric, percentage)| {
+                Metric::Percentile(Box::new(metric), percentage)
+            }),
+            (inner.clone(), duration()).prop_map(|(metric, duration)| {
+                Metric::TimeShift(Box::new(metric), duration)
+            }),
+            (inner.clone(), duration()).prop_map(|(metric, duration)| {
+                Metric::MovingAverage(Box::new(metric), duration)
+            }),
+            (inner.clone(), duration()).prop_map(|(metric, duration)| {
+                Metric::LinearRegression(Box::new(metric), duration)
+            }),
+            (inner.clone(), duration(), factor()).prop_map(|(metric, duration, threshold)| {
+                Metric::TimeLeftForecast(Box::new(metric), duration, threshold)
+            }),
+            vec(inner.clone(), 1..4).prop_map(Metric::Group),
+            inner.clone().prop_map(|metric| Metric::Stack(Box::new(metric))),
+            (inner.clone(), display_name()).prop_map(|(metric, display_name)| {
+                Metric::Alias(Box::new(metric), display_name)
+            }),
+            (function_name(), vec(argument(inner.boxed()), 0..4)).prop_map(|(name, args)| Metric::Call { name, args }),
+        ]
+    }).boxed()
+}
+
+proptest! {
+    #[test]
+    fn test_pretty_print_round_trip(metric in metric()) {
+        prop_assert_eq!(parse_metric(&pretty_print(metric.clone())), Ok(metric));
+    }
+
+    #[test]
+    fn test_compact_print_round_trip(metric in metric()) {
+        prop_assert_eq!(parse_metric(&compact_print(metric.clone())), Ok(metric));
+    }
+
+    #[test]
+    fn test_print_idempotent(metric in metric()) {
+        let printed = pretty_print(metric.clone());
+        prop_assert_eq!(pretty_print(parse_metric(&printed).unwrap()), printed);
+        let printed = compact_print(metric);
+        prop_assert_eq!(compact_print(parse_metric(&printed).unwrap()), printed);
+    }
+
+    #[test]
+    fn test_parse_recovering(metric in metric()) {
+        let src = compact_print(metric.clone());
+        let recovery = parse_recovering(&src);
+        prop_assert_eq!(recovery.errors, vec![]);
+        prop_assert_eq!(recovery.metric, PartialMetric::Metric(metric, Span { start: 0, end: src.len() }));
+    }
+}
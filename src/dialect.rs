@@ -0,0 +1,212 @@
+use pest::Parser;
+use pest::inputs::Input;
+use pest::iterators::{Pair, Pairs};
+use serde_json::{self, Value};
+use serde_yaml;
+
+use functions::{self, Kind};
+use suggest::DURATION_UNITS;
+use {check_depth, MetricParser, ParseError, Rule, Span};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Dialect {
+    pub name: String,
+    pub functions: Option<Vec<String>>,
+    pub disabled_functions: Vec<String>,
+    pub quoted_literals: bool,
+    pub fractions: bool,
+    pub duration_units: Vec<String>,
+}
+
+impl Default for Dialect {
+    fn default() -> Dialect {
+        Dialect {
+            name: "default".to_string(),
+            functions: None,
+            disabled_functions: Vec::new(),
+            quoted_literals: true,
+            fractions: true,
+            duration_units: DURATION_UNITS.iter().map(|&(unit, _)| unit.to_string()).collect(),
+        }
+    }
+}
+
+impl Dialect {
+    pub fn from_json(src: &str) -> Result<Dialect, String> {
+        Dialect::from_value(&serde_json::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_yaml(src: &str) -> Result<Dialect, String> {
+        Dialect::from_value(&serde_yaml::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_value(value: &Value) -> Result<Dialect, String> {
+        let mut dialect = Dialect::default();
+        match value.get("name") {
+            Some(Value::String(name)) => dialect.name = name.clone(),
+            Some(_) => return Err("expected a string for name".to_string()),
+            None => dialect.name = "custom".to_string(),
+        }
+        dialect.functions = strings(value, "functions")?;
+        dialect.disabled_functions = strings(value, "disabled_functions")?.unwrap_or_default();
+        for (key, flag) in [("quoted_literals", &mut dialect.quoted_literals), ("fractions", &mut dialect.fractions)] {
+            match value.get(key) {
+                Some(&Value::Bool(b)) => *flag = b,
+                Some(_) => return Err(format!("expected a boolean for {}", key)),
+                None => {}
+            }
+        }
+        if let Some(units) = strings(value, "duration_units")? {
+            if let Some(unit) = units.iter().find(|unit| !dialect.duration_units.contains(unit)) {
+                return Err(format!("unknown duration unit: {}", unit));
+            }
+            dialect.duration_units = units;
+        }
+        Ok(dialect)
+    }
+
+    pub fn allows(&self, name: &str) -> bool {
+        self.functions.as_ref().is_none_or(|functions| functions.iter().any(|f| f == name))
+            && !self.disabled_functions.iter().any(|f| f == name)
+    }
+
+    fn unavailable(&self, span: Span, what: String) -> ParseError {
+        ParseError { span, message: format!("{} is not available in dialect {}", what, self.name) }
+    }
+}
+
+fn strings(value: &Value, key: &str) -> Result<Option<Vec<String>>, String> {
+    match value.get(key) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| value.as_str().map(|s| s.to_string()).ok_or_else(|| format!("expected strings for {}", key)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Some),
+        Some(_) => Err(format!("expected an array for {}", key)),
+        None => Ok(None),
+    }
+}
+
+fn span_of<I: Input>(pair: &Pair<Rule, I>) -> Span {
+    let span = pair.clone().into_span();
+    Span { start: span.start(), end: span.end() }
+}
+
+pub fn check(src: &str, dialect: &Dialect) -> Result<Vec<ParseError>, String> {
+    check_depth(src).map_err(|err| err.message)?;
+    let pairs = MetricParser::parse_str(Rule::whole_metrics, src).map_err(|e| format!("{}", e))?;
+    Ok(check_pairs(pairs, dialect))
+}
+
+pub(crate) fn check_pairs<I: Input>(pairs: Pairs<Rule, I>, dialect: &Dialect) -> Vec<ParseError> {
+    let mut errors = Vec::new();
+    for pair in pairs {
+        check_pair(pair, dialect, &mut errors);
+    }
+    errors
+}
+
+fn check_pair<I: Input>(pair: Pair<Rule, I>, dialect: &Dialect, errors: &mut Vec<ParseError>) {
+    if pair.as_rule() != Rule::metrics {
+        for inner in pair.into_inner() {
+            check_pair(inner, dialect, errors);
+        }
+        return;
+    }
+    let mut inner = pair.into_inner();
+    let function = match inner.next() {
+        Some(name) => {
+            if !dialect.allows(name.as_str()) {
+                errors.push(dialect.unavailable(span_of(&name), name.as_str().to_string()));
+            }
+            functions::function(name.as_str())
+        }
+        None => None,
+    };
+    for (i, arg) in inner.enumerate() {
+        let value = match arg.into_inner().next() {
+            Some(value) => value,
+            None => continue,
+        };
+        let span = span_of(&value);
+        let text = value.as_str();
+        let quoted = text.starts_with('\'') || text.starts_with('"');
+        match function.and_then(|function| function.kind(i)) {
+            Some(kind @ Kind::HostId)
+            | Some(kind @ Kind::ServiceName)
+            | Some(kind @ Kind::RoleFullName)
+            | Some(kind @ Kind::MetricName)
+                if quoted && !dialect.quoted_literals =>
+            {
+                errors.push(dialect.unavailable(span, format!("quoted {}", kind.describe())));
+            }
+            Some(Kind::Factor) if text.contains('/') && !dialect.fractions => {
+                errors.push(dialect.unavailable(span, format!("fraction {}", text)));
+            }
+            Some(Kind::Duration) => {
+                if quoted && !dialect.quoted_literals {
+                    errors.push(dialect.unavailable(span, "quoted duration".to_string()));
+                }
+                let inner = text.trim_matches(['\'', '"']);
+                let unit_start = inner.find(|c: char| !c.is_ascii_digit()).unwrap_or(inner.len());
+                let unit = &inner[unit_start..];
+                if !dialect.duration_units.iter().any(|u| u == unit) {
+                    let start = span.start + if quoted { 1 } else { 0 } + unit_start;
+                    let span = Span { start, end: start + unit.len() };
+                    errors.push(dialect.unavailable(span, format!("duration unit {}", unit)));
+                }
+            }
+            _ => check_pair(value, dialect, errors),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(src: &str, dialect: &Dialect) -> Vec<(usize, String)> {
+        check(src, dialect).unwrap().into_iter().map(|err| (err.span.start, err.message)).collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let src = "alias(scale(timeShift(host('22CXRB3pZmu', loadavg5), 1mo), 1/2), 'half')";
+        assert!(messages(src, &Dialect::default()).is_empty());
+        let dialect = Dialect::from_yaml(
+            "name: legacy\ndisabled_functions: [alias]\nquoted_literals: false\nfractions: false\nduration_units: [m, h, d, w]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            messages(src, &dialect),
+            vec![
+                (0, "alias is not available in dialect legacy".to_string()),
+                (27, "quoted host id is not available in dialect legacy".to_string()),
+                (54, "duration unit mo is not available in dialect legacy".to_string()),
+                (59, "fraction 1/2 is not available in dialect legacy".to_string()),
+            ]
+        );
+        assert!(messages("newFunction(host(a, b), '1y')", &dialect).is_empty());
+        let dialect = Dialect::from_json(r#"{"name": "next", "functions": ["host", "newFunction"]}"#).unwrap();
+        assert!(messages("newFunction(host(a, b), '1y')", &dialect).is_empty());
+        assert_eq!(
+            messages("avg(otherFunction(host(a, b)))", &dialect),
+            vec![
+                (0, "avg is not available in dialect next".to_string()),
+                (4, "otherFunction is not available in dialect next".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_value() {
+        let dialect = Dialect::from_json(r#"{"functions": ["host", "avg"]}"#).unwrap();
+        assert_eq!(dialect.name, "custom");
+        assert_eq!(dialect.functions, Some(vec!["host".to_string(), "avg".to_string()]));
+        let dialect = Dialect::from_json(r#"{"disabled_functions": ["alias", "newFunction"]}"#).unwrap();
+        assert!(dialect.allows("host") && dialect.allows("otherFunction"));
+        assert!(!dialect.allows("alias") && !dialect.allows("newFunction"));
+        assert!(Dialect::from_json(r#"{"duration_units": ["s"]}"#).is_err());
+        assert!(Dialect::from_json(r#"{"fractions": "no"}"#).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+
+use functions;
+use source::{fetch, MetricSource, Point, Series};
+use {compact_print, Metric};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Evaluated {
+    pub series: Series,
+    pub stacked: bool,
+}
+
+pub fn evaluate<S: MetricSource + ?Sized>(source: &S, metric: &Metric, from: i64, to: i64) -> Result<Vec<Evaluated>, String> {
+    match *metric {
+        Metric::Host(..) | Metric::Service(..) | Metric::Role(..) | Metric::RoleSlot(..) => Ok(fetch(source, metric, from, to)?
+            .into_iter()
+            .map(|series| Evaluated { series, stacked: false })
+            .collect()),
+        Metric::Avg(ref inner) => aggregate(metric, evaluate(source, inner, from, to)?, |values| {
+            values.iter().sum::<f64>() / values.len() as f64
+        }),
+        Metric::Max(ref inner) => aggregate(metric, evaluate(source, inner, from, to)?, |values| {
+            values.iter().cloned().fold(f64::NEG_INFINITY, f64::max)
+        }),
+        Metric::Min(ref inner) => aggregate(metric, evaluate(source, inner, from, to)?, |values| {
+            values.iter().cloned().fold(f64::INFINITY, f64::min)
+        }),
+        Metric::Sum(ref inner) => aggregate(metric, evaluate(source, inner, from, to)?, |values| values.iter().sum()),
+        Metric::Product(ref inner) => {
+            aggregate(metric, evaluate(source, inner, from, to)?, |values| values.iter().product())
+        }
+        Metric::Diff(ref metric1, ref metric2) => combine(
+            metric,
+            evaluate(source, metric1, from, to)?,
+            evaluate(source, metric2, from, to)?,
+            |x, y| Some(x - y),
+        ),
+        Metric::Divide(ref metric1, ref metric2) => combine(
+            metric,
+            evaluate(source, metric1, from, to)?,
+            evaluate(source, metric2, from, to)?,
+            |x, y| if y == 0.0 { None } else { Some(x / y) },
+        ),
+        Metric::Scale(ref metric, ref factor) => {
+            let factor = factor.value();
+            Ok(map_values(evaluate(source, metric, from, to)?, |value| value * factor))
+        }
+        Metric::Offset(ref metric, ref factor) => {
+            let factor = factor.value();
+            Ok(map_values(evaluate(source, metric, from, to)?, |value| value + factor))
+        }
+        Metric::Percentile(ref inner, ref percentage) => {
+            let percentage = percentage.value();
+            aggregate(metric, evaluate(source, inner, from, to)?, |values| {
+                let mut values = values.to_vec();
+                values.sort_by(|x, y| x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal));
+                let rank = (percentage / 100.0 * values.len() as f64).ceil() as usize;
+                values[rank.max(1).min(values.len()) - 1]
+            })
+        }
+        Metric::TimeShift(ref metric, ref duration) => {
+            let shift = duration.seconds();
+            let mut evaluated = evaluate(source, metric, from - shift, to - shift)?;
+            for e in &mut evaluated {
+                for point in &mut e.series.points {
+                    point.time += shift;
+                }
+            }
+            Ok(evaluated)
+        }
+        Metric::MovingAverage(ref metric, ref duration) => {
+            let window = duration.seconds();
+            let mut evaluated = evaluate(source, metric, from - window, to)?;
+            for e in &mut evaluated {
+                let points = e.series.points.clone();
+                e.series.points = points
+                    .iter()
+                    .filter(|p| from <= p.time)
+                    .map(|p| {
+                        let values = points
+                            .iter()
+                            .filter(|q| p.time - window < q.time && q.time <= p.time)
+                            .map(|q| q.value)
+                            .collect::<Vec<_>>();
+                        Point { time: p.time, value: values.iter().sum::<f64>() / values.len() as f64 }
+                    })
+                    .collect();
+            }
+            Ok(evaluated)
+        }
+        Metric::LinearRegression(ref metric, ref duration) => {
+            let since = to - duration.seconds();
+            let mut evaluated = evaluate(source, metric, from.min(since), to)?;
+            for e in &mut evaluated {
+                let points = e.series.points.clone();
+                e.series.points = match regression(points.iter().filter(|p| since <= p.time)) {
+                    Some((a, b)) => points
+                        .iter()
+                        .filter(|p| from <= p.time)
+                        .map(|p| Point { time: p.time, value: a + b * p.time as f64 })
+                        .collect(),
+                    None => Vec::new(),
+                };
+            }
+            Ok(evaluated)
+        }
+        Metric::TimeLeftForecast(ref metric, ref duration, ref threshold) => {
+            let since = to - duration.seconds();
+            let threshold = threshold.value();
+            let mut evaluated = evaluate(source, metric, from.min(since), to)?;
+            for e in &mut evaluated {
+                let points = e.series.points.clone();
+                e.series.points = match regression(points.iter().filter(|p| since <= p.time)) {
+                    Some((a, b)) if b != 0.0 => points
+                        .iter()
+                        .filter(|p| from <= p.time)
+                        .map(|p| Point { time: p.time, value: (threshold - a) / b - p.time as f64 })
+                        .filter(|p| p.value >= 0.0)
+                        .collect(),
+                    _ => Vec::new(),
+                };
+            }
+            Ok(evaluated)
+        }
+        Metric::Group(ref metrics) => {
+            let mut evaluated = Vec::new();
+            for metric in metrics {
+                evaluated.extend(evaluate(source, metric, from, to)?);
+            }
+            Ok(evaluated)
+        }
+        Metric::Stack(ref metric) => Ok(evaluate(source, metric, from, to)?
+            .into_iter()
+            .map(|e| Evaluated { stacked: true, ..e })
+            .collect()),
+        Metric::Alias(ref metric, ref display_name) => Ok(evaluate(source, metric, from, to)?
+            .into_iter()
+            .map(|mut e| {
+                e.series.name = display_name.clone();
+                e
+            })
+            .collect()),
+        Metric::Call { ref name, .. } if functions::function(name).is_some() => {
+            Err(format!("cannot evaluate {} yet", name))
+        }
+        Metric::Call { ref name, .. } => Err(format!("cannot evaluate unknown function: {}", name)),
+    }
+}
+
+fn align(evaluated: &[Evaluated]) -> BTreeMap<i64, Vec<f64>> {
+    let mut values = BTreeMap::new();
+    for e in evaluated {
+        for point in &e.series.points {
+            values.entry(point.time).or_insert_with(Vec::new).push(point.value);
+        }
+    }
+    values
+}
+
+fn aggregate<F: Fn(&[f64]) -> f64>(metric: &Metric, evaluated: Vec<Evaluated>, f: F) -> Result<Vec<Evaluated>, String> {
+    if evaluated.is_empty() {
+        return Ok(Vec::new());
+    }
+    let points = align(&evaluated)
+        .into_iter()
+        .map(|(time, values)| Point { time, value: f(&values) })
+        .collect();
+    Ok(vec![Evaluated {
+        series: Series { name: compact_print(metric.clone()), points },
+        stacked: false,
+    }])
+}
+
+fn single(metric: &Metric, mut evaluated: Vec<Evaluated>) -> Result<Series, String> {
+    if evaluated.len() == 1 {
+        Ok(evaluated.remove(0).series)
+    } else {
+        Err(format!(
+            "expected a single series but got {}: {}",
+            evaluated.len(),
+            compact_print(metric.clone())
+        ))
+    }
+}
+
+fn combine<F: Fn(f64, f64) -> Option<f64>>(
+    metric: &Metric,
+    evaluated1: Vec<Evaluated>,
+    evaluated2: Vec<Evaluated>,
+    f: F,
+) -> Result<Vec<Evaluated>, String> {
+    let (series1, series2) = (single(metric, evaluated1)?, single(metric, evaluated2)?);
+    let values2 = series2.points.iter().map(|p| (p.time, p.value)).collect::<BTreeMap<_, _>>();
+    let points = series1
+        .points
+        .iter()
+        .filter_map(|p| values2.get(&p.time).and_then(|&y| f(p.value, y)).map(|value| Point { time: p.time, value }))
+        .collect();
+    Ok(vec![Evaluated {
+        series: Series { name: compact_print(metric.clone()), points },
+        stacked: false,
+    }])
+}
+
+fn map_values<F: Fn(f64) -> f64>(evaluated: Vec<Evaluated>, f: F) -> Vec<Evaluated> {
+    evaluated
+        .into_iter()
+        .map(|mut e| {
+            for point in &mut e.series.points {
+                point.value = f(point.value);
+            }
+            e
+        })
+        .collect()
+}
+
+fn regression<'a, I: Iterator<Item = &'a Point>>(points: I) -> Option<(f64, f64)> {
+    let points = points.map(|p| (p.time as f64, p.value)).collect::<Vec<_>>();
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|&(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|&(_, y)| y).sum::<f64>() / n;
+    let sxx = points.iter().map(|&(x, _)| (x - mean_x) * (x - mean_x)).sum::<f64>();
+    let sxy = points.iter().map(|&(x, y)| (x - mean_x) * (y - mean_y)).sum::<f64>();
+    if sxx == 0.0 {
+        return None;
+    }
+    let b = sxy / sxx;
+    Some((mean_y - b * mean_x, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_metric;
+    use source::LocalSource;
+
+    fn source() -> LocalSource {
+        LocalSource::from_json(
+            r#"{
+              "hosts": [
+                { "id": "22CXRB3pZmu", "name": "db01", "roles": { "Blog": ["db"] } },
+                { "id": "22CXRB3pZmv", "name": "db02", "roles": { "Blog": ["db"] } }
+              ],
+              "hostMetrics": {
+                "22CXRB3pZmu": { "loadavg5": [{ "time": 0, "value": 1 }, { "time": 60, "value": 2 }, { "time": 120, "value": 3 }] },
+                "22CXRB3pZmv": { "loadavg5": [{ "time": 0, "value": 3 }, { "time": 60, "value": 4 }, { "time": 120, "value": 5 }] }
+              },
+              "serviceMetrics": {
+                "Blog": {
+                  "access.2xx": [{ "time": 0, "value": 90 }, { "time": 60, "value": 80 }],
+                  "access.5xx": [{ "time": 0, "value": 10 }, { "time": 60, "value": 20 }]
+                }
+              }
+            }"#,
+        ).unwrap()
+    }
+
+    fn values(src: &str, from: i64, to: i64) -> Vec<Vec<(i64, f64)>> {
+        evaluate(&source(), &parse_metric(src).unwrap(), from, to)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.series.points.iter().map(|p| (p.time, p.value)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(values("role(Blog:db, loadavg5)", 0, 60), vec![vec![(0, 1.0), (60, 2.0)], vec![(0, 3.0), (60, 4.0)]]);
+        assert_eq!(values("avg(role(Blog:db, loadavg5))", 0, 120), vec![vec![(0, 2.0), (60, 3.0), (120, 4.0)]]);
+        assert_eq!(values("max(role(Blog:db, loadavg5))", 0, 0), vec![vec![(0, 3.0)]]);
+        assert_eq!(values("min(role(Blog:db, loadavg5))", 0, 0), vec![vec![(0, 1.0)]]);
+        assert_eq!(values("sum(role(Blog:db, loadavg5))", 0, 0), vec![vec![(0, 4.0)]]);
+        assert_eq!(values("product(role(Blog:db, loadavg5))", 0, 0), vec![vec![(0, 3.0)]]);
+        assert_eq!(values("percentile(role(Blog:db, loadavg5), 50)", 0, 0), vec![vec![(0, 1.0)]]);
+        assert_eq!(
+            values("divide(service(Blog, access.5xx), sum(service(Blog, access.*)))", 0, 60),
+            vec![vec![(0, 0.1), (60, 0.2)]]
+        );
+        assert_eq!(
+            values("diff(host(22CXRB3pZmv, loadavg5), host(22CXRB3pZmu, loadavg5))", 0, 60),
+            vec![vec![(0, 2.0), (60, 2.0)]]
+        );
+        assert_eq!(values("scale(host(22CXRB3pZmu, loadavg5), 1/2)", 0, 60), vec![vec![(0, 0.5), (60, 1.0)]]);
+        assert_eq!(values("offset(host(22CXRB3pZmu, loadavg5), -1)", 0, 60), vec![vec![(0, 0.0), (60, 1.0)]]);
+        assert_eq!(values("timeShift(host(22CXRB3pZmu, loadavg5), 1m)", 60, 120), vec![vec![(60, 1.0), (120, 2.0)]]);
+        assert_eq!(values("movingAverage(host(22CXRB3pZmu, loadavg5), 2m)", 60, 120), vec![vec![(60, 1.5), (120, 2.5)]]);
+        assert_eq!(
+            values("linearRegression(host(22CXRB3pZmu, loadavg5), 2m)", 0, 120),
+            vec![vec![(0, 1.0), (60, 2.0), (120, 3.0)]]
+        );
+        assert_eq!(
+            values("timeLeftForecast(host(22CXRB3pZmu, loadavg5), 2m, 5)", 60, 120),
+            vec![vec![(60, 180.0), (120, 120.0)]]
+        );
+    }
+
+    #[test]
+    fn test_evaluate_names() {
+        let evaluated = evaluate(
+            &source(),
+            &parse_metric("group(stack(alias(host(22CXRB3pZmu, loadavg5), primary)), avg(role(Blog:db, loadavg5)))").unwrap(),
+            0,
+            120,
+        ).unwrap();
+        assert_eq!(
+            evaluated.iter().map(|e| (e.series.name.as_str(), e.stacked)).collect::<Vec<_>>(),
+            vec![("primary", true), ("avg(role(Blog:db, loadavg5))", false)]
+        );
+        assert!(evaluate(&source(), &parse_metric("diff(role(Blog:db, loadavg5), role(Blog:db, loadavg5))").unwrap(), 0, 60).is_err());
+    }
+}
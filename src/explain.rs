@@ -0,0 +1,240 @@
+use {pretty_print_factor, Argument, Duration, Factor, Metric, Percentage};
+
+const TIME_UNITS: &[(f64, &str)] =
+    &[(1.0 / 60.0, "minutes"), (1.0 / 3600.0, "hours"), (1.0 / 86400.0, "days"), (1.0 / 604800.0, "weeks")];
+
+const BYTE_UNITS: &[(f64, &str)] = &[
+    (8.0, "bits"),
+    (1.0 / 1024.0, "KiB"),
+    (1.0 / 1048576.0, "MiB"),
+    (1.0 / 1073741824.0, "GiB"),
+    (1.0 / 1099511627776.0, "TiB"),
+    (1.0 / 1e3, "kB"),
+    (1.0 / 1e6, "MB"),
+    (1.0 / 1e9, "GB"),
+    (1.0 / 1e12, "TB"),
+];
+
+const SI_PREFIXES: &[(f64, &str)] = &[(1e15, "P"), (1e12, "T"), (1e9, "G"), (1e6, "M"), (1e3, "k")];
+
+fn is_bytes(metric: &Metric) -> bool {
+    match *metric {
+        Metric::Host(_, ref metric_name)
+        | Metric::Service(_, ref metric_name)
+        | Metric::Role(_, _, ref metric_name)
+        | Metric::RoleSlot(_, _, ref metric_name) => {
+            metric_name.starts_with("memory.")
+                || metric_name.starts_with("filesystem.")
+                || metric_name.to_lowercase().contains("bytes")
+        }
+        Metric::Avg(ref metric)
+        | Metric::Max(ref metric)
+        | Metric::Min(ref metric)
+        | Metric::Sum(ref metric)
+        | Metric::Percentile(ref metric, _)
+        | Metric::TimeShift(ref metric, _)
+        | Metric::MovingAverage(ref metric, _)
+        | Metric::LinearRegression(ref metric, _)
+        | Metric::Stack(ref metric)
+        | Metric::Alias(ref metric, _) => is_bytes(metric),
+        Metric::Diff(ref metric1, ref metric2) => is_bytes(metric1) && is_bytes(metric2),
+        Metric::Group(ref metrics) => metrics.iter().all(is_bytes),
+        _ => false,
+    }
+}
+
+fn unit(units: &[(f64, &'static str)], factor: &Factor) -> Option<&'static str> {
+    let value = factor.value();
+    units
+        .iter()
+        .find(|&&(scale, _)| (value - scale).abs() <= scale * 1e-9)
+        .map(|&(_, unit)| unit)
+}
+
+fn number(factor: &Factor, bytes: bool) -> String {
+    let value = factor.value();
+    let suffix = if bytes { "B" } else { "" };
+    for &(scale, prefix) in SI_PREFIXES {
+        let count = value / scale;
+        if count.abs() >= 1.0 && count.fract() == 0.0 {
+            return format!("{} {}{}", count, prefix, suffix);
+        }
+    }
+    if bytes {
+        format!("{} bytes", pretty_print_factor(factor.clone()))
+    } else {
+        pretty_print_factor(factor.clone())
+    }
+}
+
+fn duration(duration: &Duration, last: bool) -> String {
+    let Duration(ref s) = *duration;
+    let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let count = &s[..unit_start];
+    let unit = match &s[unit_start..] {
+        "m" => "minute",
+        "h" => "hour",
+        "d" => "day",
+        "w" => "week",
+        "mo" => "month",
+        "y" => "year",
+        unit => unit,
+    };
+    match count {
+        "1" if last => unit.to_string(),
+        "1" => format!("1 {}", unit),
+        _ => format!("{} {}s", count, unit),
+    }
+}
+
+fn list(items: Vec<String>) -> String {
+    match items.split_last() {
+        Some((last, init)) if !init.is_empty() => format!("{} and {}", init.join(", "), last),
+        _ => items.join(""),
+    }
+}
+
+fn forecast(metric: &Metric, period: &Duration, threshold: &Factor, unit: &str) -> String {
+    format!(
+        "{} until {} reaches {}, forecast from the last {}",
+        unit,
+        explain(metric),
+        number(threshold, is_bytes(metric)),
+        duration(period, true)
+    )
+}
+
+fn ordinal_suffix(number: &str) -> &'static str {
+    if !number.chars().all(|c| c.is_ascii_digit()) {
+        return "th";
+    }
+    let tens = number.len().checked_sub(2).map_or("", |i| &number[i..]);
+    match (tens, number.chars().last()) {
+        ("11", _) | ("12", _) | ("13", _) => "th",
+        (_, Some('1')) => "st",
+        (_, Some('2')) => "nd",
+        (_, Some('3')) => "rd",
+        _ => "th",
+    }
+}
+
+pub fn explain(metric: &Metric) -> String {
+    match *metric {
+        Metric::Host(ref host_id, ref metric_name) => format!("{} on host {}", metric_name, host_id),
+        Metric::Service(ref service_name, ref metric_name) => format!("{} of service {}", metric_name, service_name),
+        Metric::Role(ref service_name, ref role_name, ref metric_name) => {
+            format!("{} on each host of role {}:{}", metric_name, service_name, role_name)
+        }
+        Metric::RoleSlot(ref service_name, ref role_name, ref metric_name) => {
+            format!("{} on each slot of role {}:{}", metric_name, service_name, role_name)
+        }
+        Metric::Avg(ref metric) => format!("the average of {}", explain(metric)),
+        Metric::Max(ref metric) => format!("the maximum of {}", explain(metric)),
+        Metric::Min(ref metric) => format!("the minimum of {}", explain(metric)),
+        Metric::Sum(ref metric) => format!("the sum of {}", explain(metric)),
+        Metric::Product(ref metric) => format!("the product of {}", explain(metric)),
+        Metric::Diff(ref metric1, ref metric2) => format!("{} minus {}", explain(metric1), explain(metric2)),
+        Metric::Divide(ref metric1, ref metric2) => match **metric1 {
+            Metric::Group(_) => format!("each of {} divided by {}", explain(metric1), explain(metric2)),
+            _ => format!("{} divided by {}", explain(metric1), explain(metric2)),
+        },
+        Metric::Scale(ref metric, ref factor) => match (&**metric, unit(TIME_UNITS, factor), unit(BYTE_UNITS, factor)) {
+            (Metric::TimeLeftForecast(metric, period, threshold), Some(unit), _) => {
+                forecast(metric, period, threshold, unit)
+            }
+            (Metric::Divide(metric1, metric2), _, _) if factor.value() == 100.0 => {
+                format!("{} as a percentage of {}", explain(metric1), explain(metric2))
+            }
+            (metric, _, Some(unit)) if is_bytes(metric) => format!("{} in {}", explain(metric), unit),
+            (metric, _, _) => match *factor {
+                Factor::Fraction(ref nume, ref deno) if nume == "1" => {
+                    format!("{} divided by {}", explain(metric), deno)
+                }
+                _ => format!("{} multiplied by {}", explain(metric), pretty_print_factor(factor.clone())),
+            },
+        },
+        Metric::Offset(ref metric, ref factor) => match pretty_print_factor(factor.clone()) {
+            ref value if value.starts_with('-') => format!("{} minus {}", explain(metric), &value[1..]),
+            value => format!("{} plus {}", explain(metric), value),
+        },
+        Metric::Percentile(ref metric, Percentage(ref percentage)) => {
+            format!("the {}{} percentile of {}", percentage, ordinal_suffix(percentage), explain(metric))
+        }
+        Metric::TimeShift(ref metric, ref shift) => format!("{} {} ago", explain(metric), duration(shift, false)),
+        Metric::MovingAverage(ref metric, ref period) => {
+            format!("the moving average of {} over {}", explain(metric), duration(period, false))
+        }
+        Metric::LinearRegression(ref metric, ref period) => {
+            format!("the linear trend of {} over the last {}", explain(metric), duration(period, true))
+        }
+        Metric::TimeLeftForecast(ref metric, ref period, ref threshold) => {
+            forecast(metric, period, threshold, "seconds")
+        }
+        Metric::Group(ref metrics) => list(metrics.iter().map(explain).collect()),
+        Metric::Stack(ref metric) => format!("{}, stacked", explain(metric)),
+        Metric::Alias(ref metric, ref display_name) => format!("{}, labeled \"{}\"", explain(metric), display_name),
+        Metric::Call { ref name, ref args } if args.is_empty() => format!("the result of {}", name),
+        Metric::Call { ref name, ref args } => {
+            let args = args
+                .iter()
+                .map(|arg| match *arg {
+                    Argument::Metric(ref metric) => explain(metric),
+                    Argument::Factor(ref factor) => pretty_print_factor(factor.clone()),
+                    Argument::Duration(ref period) => duration(period, false),
+                    Argument::String(ref s) => format!("\"{}\"", s),
+                })
+                .collect();
+            format!("the result of {} applied to {}", name, list(args))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_metric;
+
+    fn explain_str(src: &str) -> String {
+        explain(&parse_metric(src).unwrap())
+    }
+
+    #[test]
+    fn test_ordinal_suffix() {
+        let suffixes = ["1", "2", "3", "4", "11", "12", "13", "21", "22", "23", "95", "99.9", "111", "101"]
+            .iter()
+            .map(|n| format!("{}{}", n, ordinal_suffix(n)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            suffixes,
+            vec!["1st", "2nd", "3rd", "4th", "11th", "12th", "13th", "21st", "22nd", "23rd", "95th", "99.9th", "111th", "101st"]
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        assert_eq!(
+            explain_str("scale(timeLeftForecast(host(22CXRB3pZmu, filesystem.drive.used), 3mo, 2000000000000), 1/86400)"),
+            "days until filesystem.drive.used on host 22CXRB3pZmu reaches 2 TB, forecast from the last 3 months"
+        );
+        assert_eq!(
+            explain_str("scale(divide(service(Blog, errors), service(Blog, requests)), 100)"),
+            "errors of service Blog as a percentage of requests of service Blog"
+        );
+        assert_eq!(
+            explain_str("alias(scale(avg(role(Blog:db, memory.used)), 1/1073741824), memory)"),
+            "the average of memory.used on each host of role Blog:db in GiB, labeled \"memory\""
+        );
+        assert_eq!(
+            explain_str("group(host(22CXRB3pZmu, loadavg5), timeShift(host(22CXRB3pZmu, loadavg5), 1w), offset(linearRegression(host(22CXRB3pZmu, loadavg5), 1d), -1))"),
+            "loadavg5 on host 22CXRB3pZmu, loadavg5 on host 22CXRB3pZmu 1 week ago and the linear trend of loadavg5 on host 22CXRB3pZmu over the last day minus 1"
+        );
+        assert_eq!(
+            explain_str("percentile(movingAverage(roleSlots(Blog:app, custom.latency), 5m), 95)"),
+            "the 95th percentile of the moving average of custom.latency on each slot of role Blog:app over 5 minutes"
+        );
+        assert_eq!(
+            explain_str("newFunction(host(22CXRB3pZmu, loadavg5), 1h, 'x')"),
+            "the result of newFunction applied to loadavg5 on host 22CXRB3pZmu, 1 hour and \"x\""
+        );
+    }
+}
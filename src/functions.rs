@@ -0,0 +1,429 @@
+use suggest::{did_you_mean, suggest_duration};
+use {Arguments, Metric, Rule, Span};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Metrics,
+    HostId,
+    ServiceName,
+    RoleFullName,
+    MetricName,
+    Factor,
+    Percentage,
+    Duration,
+    DisplayName,
+}
+
+impl Kind {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Kind::Metrics => "metrics",
+            Kind::HostId => "host id",
+            Kind::ServiceName => "service name",
+            Kind::RoleFullName => "role",
+            Kind::MetricName => "metric name",
+            Kind::Factor => "factor",
+            Kind::Percentage => "percentage",
+            Kind::Duration => "duration",
+            Kind::DisplayName => "display name",
+        }
+    }
+
+    pub fn invalid(self, text: &str) -> String {
+        match self {
+            Kind::Metrics => format!("expected metrics: {}", text),
+            Kind::Duration => format!("invalid duration: {}{}", text, did_you_mean(suggest_duration(text))),
+            kind => format!("invalid {}: {}", kind.describe(), text),
+        }
+    }
+
+    pub(crate) fn rule(self) -> Option<Rule> {
+        match self {
+            Kind::Metrics => None,
+            Kind::HostId => Some(Rule::host_id_literal),
+            Kind::ServiceName => Some(Rule::service_name_literal),
+            Kind::RoleFullName => Some(Rule::role_full_name_literal),
+            Kind::MetricName => Some(Rule::metric_name_literal),
+            Kind::Factor => Some(Rule::factor),
+            Kind::Percentage => Some(Rule::double),
+            Kind::Duration => Some(Rule::duration),
+            Kind::DisplayName => Some(Rule::display_name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Parameter {
+    pub name: &'static str,
+    pub kind: Kind,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub name: &'static str,
+    pub parameters: &'static [Parameter],
+    pub variadic: bool,
+    pub inline: bool,
+    pub description: &'static str,
+    pub example: &'static str,
+    pub variant: Option<Constructor>,
+}
+
+/// Builds the typed `Metric` variant of a function from its converted arguments.
+/// Functions without one are kept as `Metric::Call`.
+pub type Constructor = fn(&mut Arguments) -> Option<Metric>;
+
+const fn param(name: &'static str, kind: Kind) -> Parameter {
+    Parameter { name, kind }
+}
+
+const METRICS: Parameter = param("metrics", Kind::Metrics);
+
+pub const FUNCTIONS: &[Function] = &[
+    Function {
+        name: "host",
+        parameters: &[param("host", Kind::HostId), param("metric", Kind::MetricName)],
+        variadic: false,
+        inline: true,
+        description: "The metric of a host.",
+        example: "host(22CXRB3pZmu, loadavg5)",
+        variant: Some(|args| Some(Metric::Host(args.string()?, args.string()?))),
+    },
+    Function {
+        name: "service",
+        parameters: &[param("service", Kind::ServiceName), param("metric", Kind::MetricName)],
+        variadic: false,
+        inline: true,
+        description: "The metric of a service.",
+        example: "service(Blog, access_count.*)",
+        variant: Some(|args| Some(Metric::Service(args.string()?, args.string()?))),
+    },
+    Function {
+        name: "role",
+        parameters: &[param("role", Kind::RoleFullName), param("metric", Kind::MetricName)],
+        variadic: false,
+        inline: true,
+        description: "The metric of each host in a role.",
+        example: "role(Blog:db, loadavg5)",
+        variant: Some(|args| {
+            let (service_name, role_name) = args.role()?;
+            Some(Metric::Role(service_name, role_name, args.string()?))
+        }),
+    },
+    Function {
+        name: "roleSlots",
+        parameters: &[param("role", Kind::RoleFullName), param("metric", Kind::MetricName)],
+        variadic: false,
+        inline: true,
+        description: "The metric of each slot of the hosts in a role.",
+        example: "roleSlots(Blog:db, loadavg5)",
+        variant: Some(|args| {
+            let (service_name, role_name) = args.role()?;
+            Some(Metric::RoleSlot(service_name, role_name, args.string()?))
+        }),
+    },
+    Function {
+        name: "avg",
+        parameters: &[METRICS],
+        variadic: false,
+        inline: true,
+        description: "The average of the series at each point in time.",
+        example: "avg(role(Blog:db, loadavg5))",
+        variant: Some(|args| Some(Metric::Avg(args.metric()?))),
+    },
+    Function {
+        name: "max",
+        parameters: &[METRICS],
+        variadic: false,
+        inline: true,
+        description: "The maximum of the series at each point in time.",
+        example: "max(role(Blog:db, loadavg5))",
+        variant: Some(|args| Some(Metric::Max(args.metric()?))),
+    },
+    Function {
+        name: "min",
+        parameters: &[METRICS],
+        variadic: false,
+        inline: true,
+        description: "The minimum of the series at each point in time.",
+        example: "min(role(Blog:db, loadavg5))",
+        variant: Some(|args| Some(Metric::Min(args.metric()?))),
+    },
+    Function {
+        name: "sum",
+        parameters: &[METRICS],
+        variadic: false,
+        inline: true,
+        description: "The sum of the series at each point in time.",
+        example: "sum(role(Blog:db, loadavg5))",
+        variant: Some(|args| Some(Metric::Sum(args.metric()?))),
+    },
+    Function {
+        name: "product",
+        parameters: &[METRICS],
+        variadic: false,
+        inline: true,
+        description: "The product of the series at each point in time.",
+        example: "product(role(Blog:db, loadavg5))",
+        variant: Some(|args| Some(Metric::Product(args.metric()?))),
+    },
+    Function {
+        name: "diff",
+        parameters: &[param("minuend", Kind::Metrics), param("subtrahend", Kind::Metrics)],
+        variadic: false,
+        inline: false,
+        description: "The difference of two series.",
+        example: "diff(host(22CXRB3pZmu, memory.total), host(22CXRB3pZmu, memory.free))",
+        variant: Some(|args| Some(Metric::Diff(args.metric()?, args.metric()?))),
+    },
+    Function {
+        name: "divide",
+        parameters: &[param("dividend", Kind::Metrics), param("divisor", Kind::Metrics)],
+        variadic: false,
+        inline: false,
+        description: "The quotient of two series.",
+        example: "divide(service(Blog, errors), service(Blog, requests))",
+        variant: Some(|args| Some(Metric::Divide(args.metric()?, args.metric()?))),
+    },
+    Function {
+        name: "scale",
+        parameters: &[METRICS, param("factor", Kind::Factor)],
+        variadic: false,
+        inline: true,
+        description: "Multiplies the series by a factor.",
+        example: "scale(host(22CXRB3pZmu, memory.used), 1/1024)",
+        variant: Some(|args| Some(Metric::Scale(args.metric()?, args.factor()?))),
+    },
+    Function {
+        name: "offset",
+        parameters: &[METRICS, param("factor", Kind::Factor)],
+        variadic: false,
+        inline: true,
+        description: "Adds a constant to the series.",
+        example: "offset(host(22CXRB3pZmu, loadavg5), -1)",
+        variant: Some(|args| Some(Metric::Offset(args.metric()?, args.factor()?))),
+    },
+    Function {
+        name: "percentile",
+        parameters: &[METRICS, param("percentage", Kind::Percentage)],
+        variadic: false,
+        inline: true,
+        description: "The percentile of the series at each point in time.",
+        example: "percentile(roleSlots(Blog:app, custom.latency), 95)",
+        variant: Some(|args| Some(Metric::Percentile(args.metric()?, args.percentage()?))),
+    },
+    Function {
+        name: "timeShift",
+        parameters: &[METRICS, param("duration", Kind::Duration)],
+        variadic: false,
+        inline: true,
+        description: "Shifts the series back in time.",
+        example: "timeShift(host(22CXRB3pZmu, loadavg5), 1w)",
+        variant: Some(|args| Some(Metric::TimeShift(args.metric()?, args.duration()?))),
+    },
+    Function {
+        name: "movingAverage",
+        parameters: &[METRICS, param("duration", Kind::Duration)],
+        variadic: false,
+        inline: true,
+        description: "The moving average of the series over the duration.",
+        example: "movingAverage(host(22CXRB3pZmu, loadavg5), 10m)",
+        variant: Some(|args| Some(Metric::MovingAverage(args.metric()?, args.duration()?))),
+    },
+    Function {
+        name: "linearRegression",
+        parameters: &[METRICS, param("duration", Kind::Duration)],
+        variadic: false,
+        inline: true,
+        description: "The linear regression of the series over the last duration.",
+        example: "linearRegression(host(22CXRB3pZmu, filesystem.drive.used), 1w)",
+        variant: Some(|args| Some(Metric::LinearRegression(args.metric()?, args.duration()?))),
+    },
+    Function {
+        name: "timeLeftForecast",
+        parameters: &[METRICS, param("duration", Kind::Duration), param("threshold", Kind::Factor)],
+        variadic: false,
+        inline: false,
+        description: "The seconds left until the series reaches the threshold, forecast from the last duration.",
+        example: "timeLeftForecast(host(22CXRB3pZmu, filesystem.drive.used), 3mo, 2000000000000)",
+        variant: Some(|args| Some(Metric::TimeLeftForecast(args.metric()?, args.duration()?, args.factor()?))),
+    },
+    Function {
+        name: "group",
+        parameters: &[METRICS],
+        variadic: true,
+        inline: false,
+        description: "Shows several series together.",
+        example: "group(host(22CXRB3pZmu, loadavg5), host(3NpZbVH2B6f, loadavg5))",
+        variant: Some(|args| Some(Metric::Group(args.metrics()?))),
+    },
+    Function {
+        name: "stack",
+        parameters: &[METRICS],
+        variadic: false,
+        inline: true,
+        description: "Stacks the series on top of each other.",
+        example: "stack(role(Blog:db, loadavg5))",
+        variant: Some(|args| Some(Metric::Stack(args.metric()?))),
+    },
+    Function {
+        name: "alias",
+        parameters: &[METRICS, param("name", Kind::DisplayName)],
+        variadic: false,
+        inline: false,
+        description: "Sets the display name of the series.",
+        example: "alias(host(22CXRB3pZmu, loadavg5), 'load average')",
+        variant: Some(|args| Some(Metric::Alias(args.metric()?, args.string()?))),
+    },
+];
+
+#[cfg(not(test))]
+pub fn all() -> impl Iterator<Item = &'static Function> {
+    FUNCTIONS.iter()
+}
+
+#[cfg(test)]
+pub fn all() -> impl Iterator<Item = &'static Function> {
+    FUNCTIONS.iter().chain(tests::REGISTRY_ONLY)
+}
+
+pub fn function(name: &str) -> Option<&'static Function> {
+    all().find(|function| function.name == name)
+}
+
+pub fn names() -> Vec<&'static str> {
+    all().map(|function| function.name).collect()
+}
+
+impl Function {
+    pub fn kind(&self, i: usize) -> Option<Kind> {
+        match self.parameters.get(i) {
+            Some(parameter) => Some(parameter.kind),
+            None if self.variadic => self.parameters.last().map(|parameter| parameter.kind),
+            None => None,
+        }
+    }
+
+    pub fn check_arity(&self, count: usize) -> Result<(), String> {
+        let expected = self.parameters.len();
+        if self.variadic && count < expected {
+            Err(format!("{} expects at least {} arguments but got {}", self.name, expected, count))
+        } else if !self.variadic && count != expected {
+            Err(format!("{} expects {} arguments but got {}", self.name, expected, count))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn signature(&self) -> String {
+        let mut parameters = self.parameters.iter().map(|parameter| parameter.name).collect::<Vec<_>>();
+        if self.variadic {
+            parameters.push("...");
+        }
+        format!("{}({})", self.name, parameters.join(", "))
+    }
+
+    pub fn help(&self) -> String {
+        let mut help = format!("{}\n  {}\n", self.signature(), self.description);
+        for parameter in self.parameters {
+            help.push_str(&format!("  {}: {}\n", parameter.name, parameter.kind.describe()));
+        }
+        help.push_str(&format!("  example: {}\n", self.example));
+        help
+    }
+}
+
+pub fn complete(src: &str) -> (Span, Vec<&'static str>) {
+    let start = src
+        .rfind(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let prefix = src[start..].to_lowercase();
+    let names = all()
+        .map(|function| function.name)
+        .filter(|name| name.to_lowercase().starts_with(&prefix))
+        .collect();
+    (Span { start, end: src.len() }, names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {compact_print, parse_metric, Argument, Duration};
+
+    pub(crate) const REGISTRY_ONLY: &[Function] = &[Function {
+        name: "registryOnly",
+        parameters: &[METRICS, param("window", Kind::Duration)],
+        variadic: false,
+        inline: true,
+        description: "A function known only by its registry entry.",
+        example: "registryOnly(host(22CXRB3pZmu, loadavg5), 5m)",
+        variant: None,
+    }];
+
+    #[test]
+    fn test_examples() {
+        for function in FUNCTIONS {
+            let metric = parse_metric(function.example).unwrap();
+            assert_eq!(metric.function_name(), function.name);
+            assert!(metric.unknown_functions().is_empty());
+            assert_eq!(compact_print(metric), function.example);
+        }
+    }
+
+    #[test]
+    fn test_check_arity() {
+        assert_eq!(function("host").unwrap().check_arity(2), Ok(()));
+        assert_eq!(
+            function("host").unwrap().check_arity(1),
+            Err("host expects 2 arguments but got 1".to_string())
+        );
+        assert_eq!(function("group").unwrap().check_arity(3), Ok(()));
+        assert_eq!(
+            function("group").unwrap().check_arity(0),
+            Err("group expects at least 1 arguments but got 0".to_string())
+        );
+        assert_eq!(function("group").unwrap().kind(3), Some(Kind::Metrics));
+        assert_eq!(function("scale").unwrap().kind(2), None);
+    }
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(complete("avg(ti"), (Span { start: 4, end: 6 }, vec!["timeShift", "timeLeftForecast"]));
+        assert_eq!(complete("ROLE").1, vec!["role", "roleSlots"]);
+        assert_eq!(complete("group(host(a, b), ").1.len(), all().count());
+    }
+
+    #[test]
+    fn test_help() {
+        assert_eq!(
+            function("timeShift").unwrap().help(),
+            "timeShift(metrics, duration)\n  Shifts the series back in time.\n  metrics: metrics\n  duration: duration\n  example: timeShift(host(22CXRB3pZmu, loadavg5), 1w)\n"
+        );
+        assert_eq!(function("group").unwrap().signature(), "group(metrics, ...)");
+    }
+
+    #[test]
+    fn test_registry_only_function() {
+        let metric = parse_metric("registryOnly( host('22CXRB3pZmu', loadavg5), '5m' )").unwrap();
+        assert_eq!(
+            metric,
+            Metric::Call {
+                name: "registryOnly".to_string(),
+                args: vec![
+                    Argument::Metric(Metric::Host("22CXRB3pZmu".to_string(), "loadavg5".to_string())),
+                    Argument::Duration(Duration("5m".to_string())),
+                ],
+            }
+        );
+        assert!(metric.unknown_functions().is_empty());
+        assert_eq!(compact_print(metric), "registryOnly(host(22CXRB3pZmu, loadavg5), 5m)");
+        assert!(parse_metric("registryOnly(host(22CXRB3pZmu, loadavg5))").is_err());
+        assert!(parse_metric("registryOnly(host(22CXRB3pZmu, loadavg5), 5min)").is_err());
+        assert_eq!(complete("avg(regis").1, vec!["registryOnly"]);
+        assert_eq!(
+            function("registryOnly").unwrap().help(),
+            "registryOnly(metrics, window)\n  A function known only by its registry entry.\n  metrics: metrics\n  window: duration\n  example: registryOnly(host(22CXRB3pZmu, loadavg5), 5m)\n"
+        );
+    }
+}
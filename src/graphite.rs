@@ -0,0 +1,471 @@
+use pest::inputs::Input;
+use pest::iterators::Pair;
+use pest::{self, Parser};
+use serde_json::{self, Value};
+use serde_yaml;
+
+use {compact_print, parse_metric, Duration, Factor, Metric, ParseError, Percentage, Span};
+
+#[derive(Parser)]
+#[grammar = "graphite.pest"]
+pub struct GraphiteParser;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PathMapping {
+    pub host: String,
+    pub service: String,
+    pub role: String,
+}
+
+impl Default for PathMapping {
+    fn default() -> PathMapping {
+        PathMapping {
+            host: "hosts.{host}.{metric}".to_string(),
+            service: "services.{service}.{metric}".to_string(),
+            role: "roles.{service}.{role}.*.{metric}".to_string(),
+        }
+    }
+}
+
+impl PathMapping {
+    pub fn from_json(src: &str) -> Result<PathMapping, String> {
+        PathMapping::from_value(&serde_json::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_yaml(src: &str) -> Result<PathMapping, String> {
+        PathMapping::from_value(&serde_yaml::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_value(value: &Value) -> Result<PathMapping, String> {
+        let mut mapping = PathMapping::default();
+        for (key, path) in [("host", &mut mapping.host), ("service", &mut mapping.service), ("role", &mut mapping.role)] {
+            match value.get(key) {
+                Some(Value::String(template)) => *path = template.clone(),
+                Some(_) => return Err(format!("expected a string for {}", key)),
+                None => {}
+            }
+        }
+        Ok(mapping)
+    }
+
+    fn path(&self, template: &str, replacements: &[(&str, &str)]) -> String {
+        replacements
+            .iter()
+            .fold(template.to_string(), |path, &(name, value)| path.replace(&format!("{{{}}}", name), value))
+    }
+
+    fn reference(&self, path: &str) -> Option<Metric> {
+        if let Some(captures) = match_template(&self.role, path) {
+            let get = |name: &str| captures.iter().find(|&&(n, _)| n == name).map(|&(_, v)| v.to_string());
+            if let (Some(service), Some(role), Some(metric)) = (get("service"), get("role"), get("metric")) {
+                return Some(Metric::Role(service, role, metric));
+            }
+        }
+        if let Some(captures) = match_template(&self.service, path) {
+            let get = |name: &str| captures.iter().find(|&&(n, _)| n == name).map(|&(_, v)| v.to_string());
+            if let (Some(service), Some(metric)) = (get("service"), get("metric")) {
+                return Some(Metric::Service(service, metric));
+            }
+        }
+        if let Some(captures) = match_template(&self.host, path) {
+            let get = |name: &str| captures.iter().find(|&&(n, _)| n == name).map(|&(_, v)| v.to_string());
+            if let (Some(host), Some(metric)) = (get("host"), get("metric")) {
+                return Some(Metric::Host(host, metric));
+            }
+        }
+        None
+    }
+}
+
+fn match_template<'a, 'b>(template: &'a str, path: &'b str) -> Option<Vec<(&'a str, &'b str)>> {
+    if template.is_empty() {
+        return if path.is_empty() { Some(Vec::new()) } else { None };
+    }
+    if !template.starts_with('{') {
+        let len = template.find('{').unwrap_or(template.len());
+        return if path.starts_with(&template[..len]) {
+            match_template(&template[len..], &path[len..])
+        } else {
+            None
+        };
+    }
+    let close = template.find('}')?;
+    let name = &template[1..close];
+    let limit = if name == "metric" { path.len() } else { path.find('.').unwrap_or(path.len()) };
+    for end in (1..limit + 1).filter(|&end| path.is_char_boundary(end)) {
+        if let Some(mut captures) = match_template(&template[close + 1..], &path[end..]) {
+            captures.insert(0, (name, &path[..end]));
+            return Some(captures);
+        }
+    }
+    None
+}
+
+fn graphite_factor(factor: &Factor) -> String {
+    match *factor {
+        Factor::Double(ref s) => s.clone(),
+        Factor::Fraction(..) => format!("{}", factor.value()),
+    }
+}
+
+fn graphite_duration(duration: &Duration) -> String {
+    let Duration(ref s) = *duration;
+    let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let unit = match &s[unit_start..] {
+        "m" => "min",
+        "mo" => "mon",
+        unit => unit,
+    };
+    format!("'{}{}'", &s[..unit_start], unit)
+}
+
+fn graphite_string(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+pub fn to_graphite(metric: &Metric, mapping: &PathMapping) -> Result<String, String> {
+    let convert = |metric: &Metric| to_graphite(metric, mapping);
+    Ok(match *metric {
+        Metric::Host(ref host_id, ref metric_name) => {
+            mapping.path(&mapping.host, &[("host", host_id), ("metric", metric_name)])
+        }
+        Metric::Service(ref service_name, ref metric_name) => {
+            mapping.path(&mapping.service, &[("service", service_name), ("metric", metric_name)])
+        }
+        Metric::Role(ref service_name, ref role_name, ref metric_name)
+        | Metric::RoleSlot(ref service_name, ref role_name, ref metric_name) => mapping.path(
+            &mapping.role,
+            &[("service", service_name), ("role", role_name), ("metric", metric_name)],
+        ),
+        Metric::Avg(ref metric) => format!("averageSeries({})", convert(metric)?),
+        Metric::Max(ref metric) => format!("maxSeries({})", convert(metric)?),
+        Metric::Min(ref metric) => format!("minSeries({})", convert(metric)?),
+        Metric::Sum(ref metric) => format!("sumSeries({})", convert(metric)?),
+        Metric::Product(ref metric) => format!("multiplySeries({})", convert(metric)?),
+        Metric::Diff(ref metric1, ref metric2) => format!("diffSeries({}, {})", convert(metric1)?, convert(metric2)?),
+        Metric::Divide(ref metric1, ref metric2) => {
+            format!("divideSeries({}, {})", convert(metric1)?, convert(metric2)?)
+        }
+        Metric::Scale(ref metric, ref factor) => format!("scale({}, {})", convert(metric)?, graphite_factor(factor)),
+        Metric::Offset(ref metric, ref factor) => format!("offset({}, {})", convert(metric)?, graphite_factor(factor)),
+        Metric::Percentile(ref metric, ref percentage) => {
+            format!("percentileOfSeries({}, {})", convert(metric)?, percentage.0)
+        }
+        Metric::TimeShift(ref metric, ref duration) => {
+            format!("timeShift({}, {})", convert(metric)?, graphite_duration(duration))
+        }
+        Metric::MovingAverage(ref metric, ref duration) => {
+            format!("movingAverage({}, {})", convert(metric)?, graphite_duration(duration))
+        }
+        Metric::LinearRegression(..) | Metric::TimeLeftForecast(..) | Metric::Call { .. } => {
+            return Err(format!("{} has no Graphite equivalent", metric.function_name()));
+        }
+        Metric::Group(ref metrics) => format!(
+            "group({})",
+            metrics.iter().map(&convert).collect::<Result<Vec<_>, _>>()?.join(", ")
+        ),
+        Metric::Stack(ref metric) => format!("stacked({})", convert(metric)?),
+        Metric::Alias(ref metric, ref display_name) => {
+            format!("alias({}, {})", convert(metric)?, graphite_string(display_name))
+        }
+    })
+}
+
+fn span_of<I: Input>(pair: &Pair<Rule, I>) -> Span {
+    let span = pair.clone().into_span();
+    Span { start: span.start(), end: span.end() }
+}
+
+fn error(span: Span, message: String) -> ParseError {
+    ParseError { span, message }
+}
+
+enum Argument {
+    Target(Metric),
+    Number(String),
+    String(String),
+    Boolean,
+}
+
+pub fn from_graphite(target: &str, mapping: &PathMapping) -> Result<Metric, ParseError> {
+    let mut pairs = GraphiteParser::parse_str(Rule::whole_target, target).map_err(|err| {
+        let span = match err {
+            pest::Error::ParsingError { ref pos, .. } | pest::Error::CustomErrorPos { ref pos, .. } => {
+                Span { start: pos.pos(), end: pos.pos() }
+            }
+            pest::Error::CustomErrorSpan { ref span, .. } => Span { start: span.start(), end: span.end() },
+        };
+        error(span, format!("{}", err))
+    })?;
+    match pairs.next().and_then(|pair| pair.into_inner().next()) {
+        Some(pair) => convert_target(pair, mapping),
+        None => Err(error(Span { start: 0, end: target.len() }, "empty target".to_string())),
+    }
+}
+
+fn convert_target<I: Input>(pair: Pair<Rule, I>, mapping: &PathMapping) -> Result<Metric, ParseError> {
+    let span = span_of(&pair);
+    let pair = match pair.as_rule() {
+        Rule::target => match pair.into_inner().next() {
+            Some(pair) => pair,
+            None => return Err(error(span, "empty target".to_string())),
+        },
+        _ => pair,
+    };
+    if pair.as_rule() == Rule::path {
+        let metric = mapping
+            .reference(pair.as_str())
+            .ok_or_else(|| error(span, format!("no mapping matches the path: {}", pair.as_str())))?;
+        return match parse_metric(&compact_print(metric.clone())) {
+            Ok(_) => Ok(metric),
+            Err(_) => Err(error(span, format!("the path maps to an invalid reference: {}", compact_print(metric)))),
+        };
+    }
+    let mut inner = pair.into_inner();
+    let name = match inner.next() {
+        Some(name) => name.as_str().to_string(),
+        None => return Err(error(span, "missing function name".to_string())),
+    };
+    const SUPPORTED: &[&str] = &[
+        "sumSeries", "sum", "averageSeries", "avg", "maxSeries", "minSeries", "multiplySeries", "diffSeries",
+        "divideSeries", "asPercent", "scale", "offset", "percentileOfSeries", "timeShift", "movingAverage", "alias",
+        "group", "stacked",
+    ];
+    if !SUPPORTED.contains(&name.as_str()) {
+        return Err(error(span, format!("unsupported function: {}", name)));
+    }
+    let mut args = Vec::new();
+    for arg in inner {
+        let arg_span = span_of(&arg);
+        let arg = match arg.into_inner().next() {
+            Some(arg) => arg,
+            None => return Err(error(arg_span, "empty argument".to_string())),
+        };
+        args.push((
+            arg_span,
+            match arg.as_rule() {
+                Rule::number => Argument::Number(arg.as_str().to_string()),
+                Rule::string => Argument::String(arg.into_inner().next().map(|s| unescape(s.as_str())).unwrap_or_default()),
+                Rule::boolean => Argument::Boolean,
+                _ => Argument::Target(convert_target(arg, mapping)?),
+            },
+        ));
+    }
+    let (min, max) = match name.as_str() {
+        "diffSeries" | "divideSeries" | "asPercent" | "scale" | "offset" | "percentileOfSeries" | "timeShift"
+        | "movingAverage" | "alias" => (2, 2),
+        "stacked" => (1, 1),
+        _ => (1, usize::MAX),
+    };
+    if args.len() < min || args.len() > max {
+        let expected = if min == max { min.to_string() } else { format!("at least {}", min) };
+        return Err(error(span, format!("{} expects {} arguments but got {}", name, expected, args.len())));
+    }
+    let mut args = args.into_iter();
+    let mut next = || match args.next() {
+        Some(arg) => Ok(arg),
+        None => Err(error(span, format!("{} expects more arguments", name))),
+    };
+    Ok(match name.as_str() {
+        "sumSeries" | "sum" => Metric::Sum(Box::new(series(&name, next)?)),
+        "averageSeries" | "avg" => Metric::Avg(Box::new(series(&name, next)?)),
+        "maxSeries" => Metric::Max(Box::new(series(&name, next)?)),
+        "minSeries" => Metric::Min(Box::new(series(&name, next)?)),
+        "multiplySeries" => Metric::Product(Box::new(series(&name, next)?)),
+        "group" => match series(&name, next)? {
+            Metric::Group(metrics) => Metric::Group(metrics),
+            metric => Metric::Group(vec![metric]),
+        },
+        "diffSeries" => {
+            let metric1 = target_argument(&name, next()?)?;
+            Metric::Diff(Box::new(metric1), Box::new(target_argument(&name, next()?)?))
+        }
+        "divideSeries" => {
+            let metric1 = target_argument(&name, next()?)?;
+            Metric::Divide(Box::new(metric1), Box::new(target_argument(&name, next()?)?))
+        }
+        "asPercent" => {
+            let metric1 = target_argument(&name, next()?)?;
+            let divided = Metric::Divide(Box::new(metric1), Box::new(target_argument(&name, next()?)?));
+            Metric::Scale(Box::new(divided), Factor::Double("100".to_string()))
+        }
+        "scale" => {
+            let metric = target_argument(&name, next()?)?;
+            Metric::Scale(Box::new(metric), Factor::Double(number_argument(&name, next()?)?))
+        }
+        "offset" => {
+            let metric = target_argument(&name, next()?)?;
+            Metric::Offset(Box::new(metric), Factor::Double(number_argument(&name, next()?)?))
+        }
+        "percentileOfSeries" => {
+            let metric = target_argument(&name, next()?)?;
+            Metric::Percentile(Box::new(metric), Percentage(number_argument(&name, next()?)?))
+        }
+        "timeShift" => {
+            let metric = target_argument(&name, next()?)?;
+            Metric::TimeShift(Box::new(metric), duration_argument(&name, next()?, true)?)
+        }
+        "movingAverage" => {
+            let metric = target_argument(&name, next()?)?;
+            Metric::MovingAverage(Box::new(metric), duration_argument(&name, next()?, false)?)
+        }
+        "alias" => {
+            let metric = target_argument(&name, next()?)?;
+            match next()? {
+                (_, Argument::String(display_name)) => Metric::Alias(Box::new(metric), display_name),
+                (span, _) => return Err(error(span, format!("{} expects a string here", name))),
+            }
+        }
+        _ => Metric::Stack(Box::new(target_argument(&name, next()?)?)),
+    })
+}
+
+fn series<F>(name: &str, mut next: F) -> Result<Metric, ParseError>
+where
+    F: FnMut() -> Result<(Span, Argument), ParseError>,
+{
+    let mut metrics = Vec::new();
+    while let Ok(arg) = next() {
+        metrics.push(target_argument(name, arg)?);
+    }
+    Ok(if metrics.len() == 1 { metrics.remove(0) } else { Metric::Group(metrics) })
+}
+
+fn target_argument(name: &str, (span, arg): (Span, Argument)) -> Result<Metric, ParseError> {
+    match arg {
+        Argument::Target(metric) => Ok(metric),
+        _ => Err(error(span, format!("{} expects a series here", name))),
+    }
+}
+
+fn number_argument(name: &str, (span, arg): (Span, Argument)) -> Result<String, ParseError> {
+    match arg {
+        Argument::Number(number) => Ok(number),
+        _ => Err(error(span, format!("{} expects a number here", name))),
+    }
+}
+
+fn duration_argument(name: &str, (span, arg): (Span, Argument), shift: bool) -> Result<Duration, ParseError> {
+    let interval = match arg {
+        Argument::String(interval) => interval,
+        Argument::Number(_) => {
+            return Err(error(span, format!("{} with a number of points is not supported", name)));
+        }
+        _ => return Err(error(span, format!("{} expects an interval here", name))),
+    };
+    let trimmed = if shift { interval.trim_start_matches('-') } else { interval.as_str() };
+    let unit_start = trimmed.find(|c: char| !c.is_ascii_digit()).unwrap_or(trimmed.len());
+    let unit = match &trimmed[unit_start..] {
+        "s" | "sec" | "seconds" => None,
+        "min" | "mins" | "minute" | "minutes" => Some("m"),
+        "h" | "hour" | "hours" => Some("h"),
+        "d" | "day" | "days" => Some("d"),
+        "w" | "week" | "weeks" => Some("w"),
+        "mon" | "month" | "months" => Some("mo"),
+        "y" | "year" | "years" => Some("y"),
+        _ => None,
+    };
+    match unit {
+        Some(unit) if unit_start > 0 => format!("{}{}", &trimmed[..unit_start], unit)
+            .parse()
+            .map_err(|e| error(span, e)),
+        _ => Err(error(span, format!("unsupported interval: {}", interval))),
+    }
+}
+
+fn unescape(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' { chars.next().unwrap_or(c) } else { c });
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_metric;
+
+    fn graphite(src: &str, mapping: &PathMapping) -> Result<String, String> {
+        to_graphite(&parse_metric(src).unwrap(), mapping)
+    }
+
+    #[test]
+    fn test_to_graphite() {
+        let mapping = PathMapping::default();
+        assert_eq!(
+            graphite(
+                "alias(group(sum(role(Blog:app, custom.requests)), timeShift(avg(host(22CXRB3pZmu, loadavg5)), 1w)), \"Blog's load\")",
+                &mapping
+            ),
+            Ok("alias(group(sumSeries(roles.Blog.app.*.custom.requests), timeShift(averageSeries(hosts.22CXRB3pZmu.loadavg5), '1w')), 'Blog\\'s load')".to_string())
+        );
+        assert_eq!(
+            graphite("scale(divide(service(Blog, errors), service(Blog, requests)), 1/60)", &mapping),
+            Ok("scale(divideSeries(services.Blog.errors, services.Blog.requests), 0.016666666666666666)".to_string())
+        );
+        assert_eq!(
+            graphite("movingAverage(offset(host(22CXRB3pZmu, loadavg5), -1), 10m)", &mapping),
+            Ok("movingAverage(offset(hosts.22CXRB3pZmu.loadavg5, -1), '10min')".to_string())
+        );
+        assert_eq!(
+            graphite("group(host(22CXRB3pZmu, loadavg5), timeLeftForecast(host(22CXRB3pZmu, loadavg5), 3d, 100))", &mapping),
+            Err("timeLeftForecast has no Graphite equivalent".to_string())
+        );
+    }
+
+    fn import(target: &str) -> Result<String, (Span, String)> {
+        from_graphite(target, &PathMapping::default()).map(compact_print).map_err(|e| (e.span, e.message))
+    }
+
+    #[test]
+    fn test_from_graphite() {
+        assert_eq!(
+            import("sumSeries(hosts.22CXRB3pZmu.loadavg5, hosts.3NpZbVH2B6f.loadavg5)"),
+            Ok("sum(group(host(22CXRB3pZmu, loadavg5), host(3NpZbVH2B6f, loadavg5)))".to_string())
+        );
+        assert_eq!(
+            import("alias(asPercent(services.Blog.custom.errors, services.Blog.custom.requests), 'error \\'rate\\'')"),
+            Ok("alias(scale(divide(service(Blog, custom.errors), service(Blog, custom.requests)), 100), \"error 'rate'\")".to_string())
+        );
+        assert_eq!(
+            import("group(timeShift(averageSeries(roles.Blog.db.*.loadavg5), '-1week'), movingAverage(hosts.22CXRB3pZmu.loadavg5, \"10min\"))"),
+            Ok("group(timeShift(avg(role(Blog:db, loadavg5)), 1w), movingAverage(host(22CXRB3pZmu, loadavg5), 10m))".to_string())
+        );
+        assert_eq!(
+            import("scale(stacked(hosts.22CXRB3pZmu.loadavg5), 0.5)"),
+            Ok("scale(stack(host(22CXRB3pZmu, loadavg5)), 0.5)".to_string())
+        );
+        assert_eq!(
+            import("sumSeries(highestCurrent(hosts.*.loadavg5, 3))"),
+            Err((Span { start: 10, end: 45 }, "unsupported function: highestCurrent".to_string()))
+        );
+        assert_eq!(
+            import("movingAverage(hosts.22CXRB3pZmu.loadavg5, 10)"),
+            Err((Span { start: 42, end: 44 }, "movingAverage with a number of points is not supported".to_string()))
+        );
+        assert_eq!(
+            import("averageSeries(hosts.*.loadavg5)"),
+            Err((Span { start: 14, end: 30 }, "the path maps to an invalid reference: host(*, loadavg5)".to_string()))
+        );
+        assert_eq!(
+            import("diffSeries(hosts.22CXRB3pZmu.loadavg5)"),
+            Err((Span { start: 0, end: 38 }, "diffSeries expects 2 arguments but got 1".to_string()))
+        );
+        assert_eq!(
+            import("carbon.agents.cpuUsage"),
+            Err((Span { start: 0, end: 22 }, "no mapping matches the path: carbon.agents.cpuUsage".to_string()))
+        );
+        assert!(import("sumSeries(").is_err());
+    }
+
+    #[test]
+    fn test_path_mapping() {
+        let mapping = PathMapping::from_json(r#"{"host": "servers.{host}.{metric}"}"#).unwrap();
+        assert_eq!(mapping.host, "servers.{host}.{metric}");
+        assert_eq!(mapping.role, PathMapping::default().role);
+        assert_eq!(graphite("host(22CXRB3pZmu, loadavg5)", &mapping), Ok("servers.22CXRB3pZmu.loadavg5".to_string()));
+        assert!(PathMapping::from_yaml("host: 1").is_err());
+    }
+}
@@ -0,0 +1,367 @@
+use recover::parse_recovering;
+use {parse_metric_spanned, pretty_print, Span};
+
+pub const DEFAULT_KEYS: &[&str] = &["expression"];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Attribute {
+    pub key: String,
+    pub span: Span,
+    pub value: String,
+    pub heredoc: bool,
+    pub interpolated: bool,
+    offsets: Vec<usize>,
+}
+
+impl Attribute {
+    pub fn source_offset(&self, pos: usize) -> usize {
+        match self.offsets.get(pos) {
+            Some(&offset) => offset,
+            None => self.offsets.last().map(|&offset| offset + 1).unwrap_or(self.span.start),
+        }
+    }
+}
+
+struct Scanner<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        let (line, col) = Span { start: self.pos, end: self.pos }.line_col(self.src);
+        format!("{}:{}: {}", line, col, message)
+    }
+
+    fn skip_line(&mut self) {
+        self.pos += self.rest().find('\n').unwrap_or(self.rest().len());
+    }
+
+    fn skip_inline_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t']).len();
+    }
+
+    fn skip_interpolation(&mut self) -> Result<(), String> {
+        let mut depth = 0;
+        while let Some(c) = self.rest().chars().next() {
+            match c {
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += 1;
+                        return Ok(());
+                    }
+                }
+                '"' => {
+                    self.string()?;
+                    continue;
+                }
+                _ => {}
+            }
+            self.pos += c.len_utf8();
+        }
+        Err(self.error("unterminated interpolation"))
+    }
+
+    fn string(&mut self) -> Result<(String, Vec<usize>, bool), String> {
+        let start = self.pos;
+        self.pos += 1;
+        let (mut value, mut offsets, mut interpolated) = (String::new(), Vec::new(), false);
+        while let Some(c) = self.rest().chars().next() {
+            let at = self.pos;
+            let rest = self.rest();
+            if c == '"' {
+                self.pos += 1;
+                return Ok((value, offsets, interpolated));
+            } else if c == '\n' {
+                break;
+            } else if rest.starts_with("$${") || rest.starts_with("%%{") {
+                value.push_str(&rest[1..3]);
+                offsets.extend(&[at + 1, at + 2]);
+                self.pos += 3;
+            } else if rest.starts_with("${") || rest.starts_with("%{") {
+                interpolated = true;
+                self.pos += 1;
+                self.skip_interpolation()?;
+                value.push_str(&self.src[at..self.pos]);
+                offsets.extend(at..self.pos);
+            } else if c == '\\' {
+                let escaped = match rest[1..].chars().next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some(c @ '"') | Some(c @ '\\') => c,
+                    _ => return Err(self.error("unsupported escape sequence")),
+                };
+                value.push(escaped);
+                offsets.push(at);
+                self.pos += 2;
+            } else {
+                value.push(c);
+                offsets.extend(at..at + c.len_utf8());
+                self.pos += c.len_utf8();
+            }
+        }
+        self.pos = start;
+        Err(self.error("unterminated string"))
+    }
+
+    fn heredoc(&mut self) -> Result<(Span, String, Vec<usize>, bool), String> {
+        let start = self.pos;
+        let rest = &self.rest()[2..];
+        let indented = rest.starts_with('-');
+        let marker_start = if indented { 3 } else { 2 };
+        let marker = self.rest()[marker_start..]
+            .split(['\n', '\r'])
+            .next()
+            .unwrap_or("")
+            .trim_end();
+        if marker.is_empty() || !marker.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(self.error("invalid heredoc marker"));
+        }
+        self.skip_line();
+        self.pos += 1;
+        let body_start = self.pos;
+        let mut lines = Vec::new();
+        loop {
+            if self.pos >= self.src.len() {
+                self.pos = start;
+                return Err(self.error("unterminated heredoc"));
+            }
+            let line_start = self.pos;
+            self.skip_line();
+            let line = self.src[line_start..self.pos].trim_end_matches('\r');
+            if line.trim() == marker {
+                self.pos = line_start + line.len() - line.trim_start().len() + marker.len();
+                break;
+            }
+            lines.push((line_start, line));
+            self.pos += 1;
+        }
+        let indent = if indented {
+            lines
+                .iter()
+                .filter(|&&(_, line)| !line.trim().is_empty())
+                .map(|&(_, line)| line.len() - line.trim_start().len())
+                .min()
+                .unwrap_or(0)
+        } else {
+            0
+        };
+        let (mut value, mut offsets) = (String::new(), Vec::new());
+        for (line_start, line) in lines {
+            let skip = indent.min(line.len());
+            value.push_str(&line[skip..]);
+            value.push('\n');
+            offsets.extend(line_start + skip..line_start + line.len() + 1);
+        }
+        let unescaped = value.replace("$${", "").replace("%%{", "");
+        let interpolated = unescaped.contains("${") || unescaped.contains("%{");
+        let end = lines_end(self.src, body_start, self.pos, marker);
+        Ok((Span { start: body_start, end }, value, offsets, interpolated))
+    }
+}
+
+fn lines_end(src: &str, body_start: usize, marker_end: usize, marker: &str) -> usize {
+    let marker_start = marker_end - marker.len();
+    let line_start = src[..marker_start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    line_start.max(body_start)
+}
+
+pub fn find_attributes(src: &str, keys: &[&str]) -> Result<Vec<Attribute>, String> {
+    let mut scanner = Scanner { src, pos: 0 };
+    let mut attributes = Vec::new();
+    while let Some(c) = scanner.rest().chars().next() {
+        let rest = scanner.rest();
+        if c == '#' || rest.starts_with("//") {
+            scanner.skip_line();
+        } else if rest.starts_with("/*") {
+            scanner.pos += rest.find("*/").map(|i| i + 2).ok_or_else(|| scanner.error("unterminated comment"))?;
+        } else if c == '"' {
+            scanner.string()?;
+        } else if rest.starts_with("<<") {
+            scanner.heredoc()?;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-')).unwrap_or(rest.len());
+            let key = &rest[..len];
+            scanner.pos += len;
+            if !keys.contains(&key) {
+                continue;
+            }
+            scanner.skip_inline_whitespace();
+            if !scanner.rest().starts_with('=') || scanner.rest().starts_with("==") {
+                continue;
+            }
+            scanner.pos += 1;
+            scanner.skip_inline_whitespace();
+            let start = scanner.pos;
+            if scanner.rest().starts_with('"') {
+                let (value, offsets, interpolated) = scanner.string()?;
+                let span = Span { start, end: scanner.pos };
+                attributes.push(Attribute { key: key.to_string(), span, value, heredoc: false, interpolated, offsets });
+            } else if scanner.rest().starts_with("<<") {
+                let (span, value, offsets, interpolated) = scanner.heredoc()?;
+                attributes.push(Attribute { key: key.to_string(), span, value, heredoc: true, interpolated, offsets });
+            }
+        } else {
+            scanner.pos += c.len_utf8();
+        }
+    }
+    Ok(attributes)
+}
+
+pub fn check_attributes(src: &str, keys: &[&str]) -> Result<Vec<(Span, String)>, String> {
+    let mut errors = Vec::new();
+    for attribute in find_attributes(src, keys)? {
+        if attribute.interpolated {
+            continue;
+        }
+        if let Err(err) = parse_metric_spanned(&attribute.value) {
+            let offset = attribute.source_offset(err.span.start);
+            errors.push((Span { start: offset, end: offset }, err.summary().to_string()));
+        }
+    }
+    Ok(errors)
+}
+
+pub fn unknown_functions(src: &str, keys: &[&str]) -> Result<Vec<(Span, String)>, String> {
+    let mut warnings = Vec::new();
+    for attribute in find_attributes(src, keys)? {
+        if attribute.interpolated {
+            continue;
+        }
+        for warning in parse_recovering(&attribute.value).warnings {
+            let offset = attribute.source_offset(warning.span.start);
+            warnings.push((Span { start: offset, end: offset }, warning.message));
+        }
+    }
+    Ok(warnings)
+}
+
+pub fn format_heredocs(src: &str, keys: &[&str]) -> Result<String, String> {
+    let mut output = String::new();
+    let mut last = 0;
+    for attribute in find_attributes(src, keys)? {
+        if !attribute.heredoc || attribute.interpolated {
+            continue;
+        }
+        let metric = parse_metric_spanned(&attribute.value).map_err(|err| {
+            let (line, col) = Span { start: attribute.source_offset(err.span.start), end: 0 }.line_col(src);
+            format!("{}:{}: {}", line, col, err.summary())
+        })?;
+        let body = &src[attribute.span.start..attribute.span.end];
+        let indent = body
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| &line[..line.len() - line.trim_start().len()])
+            .min_by_key(|indent| indent.len())
+            .unwrap_or("");
+        output.push_str(&src[last..attribute.span.start]);
+        for line in pretty_print(metric).lines() {
+            output.push_str(indent);
+            output.push_str(line);
+            output.push('\n');
+        }
+        last = attribute.span.end;
+    }
+    output.push_str(&src[last..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN_TF: &str = r#"# expression = "ignored"
+resource "mackerel_monitor" "load" {
+  name = "load"
+  expression {
+    expression = "max(roleSlots(\"Blog:db\", loadavg5))"
+    operator   = ">"
+  }
+}
+
+resource "mackerel_monitor" "invalid" {
+  expression {
+    expression = "avg(host(22CXRB3pZmu))"
+  }
+}
+
+resource "mackerel_dashboard" "blog" {
+  graph {
+    expression {
+      expression = <<-EOT
+        group(host(22CXRB3pZmu, loadavg5),
+              avg(roleSlots(Blog:db, loadavg5)))
+      EOT
+    }
+  }
+  graph {
+    expression {
+      expression = "avg(role(${var.service}:db, loadavg5))"
+    }
+  }
+}
+"#;
+
+    #[test]
+    fn test_find_attributes() {
+        let attributes = find_attributes(MAIN_TF, DEFAULT_KEYS).unwrap();
+        assert_eq!(
+            attributes.iter().map(|a| (a.value.as_str(), a.heredoc, a.interpolated)).collect::<Vec<_>>(),
+            vec![
+                ("max(roleSlots(\"Blog:db\", loadavg5))", false, false),
+                ("avg(host(22CXRB3pZmu))", false, false),
+                ("group(host(22CXRB3pZmu, loadavg5),\n      avg(roleSlots(Blog:db, loadavg5)))\n", true, false),
+                ("avg(role(${var.service}:db, loadavg5))", false, true),
+            ]
+        );
+        let escaped = find_attributes("expression = <<EOT\nalias(host(a, b), '%%{x} $${y}')\nEOT\n", DEFAULT_KEYS).unwrap();
+        assert!(!escaped[0].interpolated);
+        let templated = find_attributes("expression = <<EOT\n%{ for h in hosts }host(h, b)%{ endfor }\nEOT\n", DEFAULT_KEYS);
+        assert!(templated.unwrap()[0].interpolated);
+        assert!(find_attributes("expression = \"avg(\n", DEFAULT_KEYS).is_err());
+        assert!(find_attributes("expression = <<EOT\navg(\n", DEFAULT_KEYS).is_err());
+    }
+
+    #[test]
+    fn test_check_attributes() {
+        let errors = check_attributes(MAIN_TF, DEFAULT_KEYS).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0.line_col(MAIN_TF), (12, 23));
+        assert_eq!(errors[0].1, "host expects 2 arguments but got 1");
+        let src = "expression = \"group(host(\\\"a\\\", b), avg(host(x)))\"";
+        let errors = check_attributes(src, DEFAULT_KEYS).unwrap();
+        assert_eq!(errors[0].0.line_col(src), (1, 41));
+    }
+
+    #[test]
+    fn test_unknown_functions() {
+        assert!(unknown_functions(MAIN_TF, DEFAULT_KEYS).unwrap().is_empty());
+        let src = MAIN_TF.replace("avg(roleSlots(Blog:db", "avg(roleSlot(Blog:db");
+        let warnings = unknown_functions(&src, DEFAULT_KEYS).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0.line_col(&src), (21, 19));
+        assert_eq!(warnings[0].1, "unknown function: roleSlot (did you mean roleSlots?)");
+    }
+
+    #[test]
+    fn test_format_heredocs() {
+        let src = MAIN_TF.replace("avg(host(22CXRB3pZmu))", "avg(host(22CXRB3pZmu, loadavg5))");
+        let formatted = format_heredocs(&src, DEFAULT_KEYS).unwrap();
+        assert_eq!(
+            formatted,
+            src.replace(
+                "        group(host(22CXRB3pZmu, loadavg5),\n              avg(roleSlots(Blog:db, loadavg5)))\n",
+                "        group(\n          host(22CXRB3pZmu, loadavg5),\n          avg(roleSlots(Blog:db, loadavg5))\n        )\n"
+            )
+        );
+        assert_eq!(format_heredocs(&formatted, DEFAULT_KEYS).unwrap(), formatted);
+    }
+}
@@ -0,0 +1,306 @@
+use serde_json::{self, Value};
+
+use recover::parse_recovering;
+use {compact_print, parse_metric_spanned, Span};
+
+pub const DEFAULT_KEYS: &[&str] = &["expression"];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expression {
+    pub path: String,
+    pub span: Span,
+    pub value: String,
+    offsets: Vec<usize>,
+}
+
+impl Expression {
+    pub fn source_offset(&self, pos: usize) -> usize {
+        match self.offsets.get(pos) {
+            Some(&offset) => offset,
+            None => self.span.end - 1,
+        }
+    }
+
+    fn source_span(&self, span: Span) -> Span {
+        let start = self.source_offset(span.start);
+        Span { start, end: start }
+    }
+}
+
+struct Scanner<'a, 'b> {
+    src: &'a str,
+    pos: usize,
+    keys: &'b [&'b str],
+    expressions: Vec<Expression>,
+}
+
+impl<'a, 'b> Scanner<'a, 'b> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(format!("expected {} at {}", c as char, self.pos))
+        }
+    }
+
+    fn string(&mut self) -> Result<(Span, String, Vec<usize>), String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        self.expect(b'"')?;
+        let (mut value, mut offsets) = (String::new(), Vec::new());
+        while let Some(c) = self.src[self.pos..].chars().next() {
+            let at = self.pos;
+            let (c, len) = match c {
+                '"' => {
+                    self.pos += 1;
+                    return Ok((Span { start, end: self.pos }, value, offsets));
+                }
+                '\\' => unescape(&self.src[at..]).ok_or_else(|| format!("invalid escape sequence at {}", at))?,
+                c => (c, c.len_utf8()),
+            };
+            value.push(c);
+            offsets.extend(::std::iter::repeat_n(at, c.len_utf8()));
+            self.pos += len;
+        }
+        Err(format!("unterminated string at {}", start))
+    }
+
+    fn value(&mut self, path: &str) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                loop {
+                    let (_, key, _) = self.string()?;
+                    self.expect(b':')?;
+                    let path = child_path(path, &key);
+                    self.skip_whitespace();
+                    if self.peek() == Some(b'"') && self.keys.contains(&key.as_str()) {
+                        let (span, value, offsets) = self.string()?;
+                        self.expressions.push(Expression { path, span, value, offsets });
+                    } else {
+                        self.value(&path)?;
+                    }
+                    self.skip_whitespace();
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        return self.expect(b'}');
+                    }
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                let mut index = 0;
+                loop {
+                    self.value(&format!("{}[{}]", path, index))?;
+                    index += 1;
+                    self.skip_whitespace();
+                    if self.peek() == Some(b',') {
+                        self.pos += 1;
+                    } else {
+                        return self.expect(b']');
+                    }
+                }
+            }
+            Some(b'"') => self.string().map(|_| ()),
+            _ => {
+                let rest = &self.src[self.pos..];
+                self.pos += rest
+                    .find(|c: char| c == ',' || c == '}' || c == ']' || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                Ok(())
+            }
+        }
+    }
+}
+
+fn unescape(escape: &str) -> Option<(char, usize)> {
+    let hex = |i: usize| escape.get(i..i + 4).and_then(|digits| u32::from_str_radix(digits, 16).ok());
+    let c = match escape.as_bytes().get(1)? {
+        b'"' => '"',
+        b'\\' => '\\',
+        b'/' => '/',
+        b'b' => '\u{8}',
+        b'f' => '\u{c}',
+        b'n' => '\n',
+        b'r' => '\r',
+        b't' => '\t',
+        b'u' => {
+            let high = hex(2)?;
+            if (0xd800..0xdc00).contains(&high) && escape[6..].starts_with("\\u") {
+                let low = hex(8)?;
+                return ::std::char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low.checked_sub(0xdc00)? & 0x3ff))
+                    .map(|c| (c, 12));
+            }
+            return ::std::char::from_u32(high).map(|c| (c, 6));
+        }
+        _ => return None,
+    };
+    Some((c, 2))
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        format!("{}.{}", path, key)
+    } else {
+        format!("{}[{}]", path, Value::String(key.to_string()))
+    }
+}
+
+pub fn find_expressions(src: &str, keys: &[&str]) -> Result<Vec<Expression>, String> {
+    serde_json::from_str::<Value>(src).map_err(|e| format!("{}", e))?;
+    let mut scanner = Scanner { src, pos: 0, keys, expressions: Vec::new() };
+    scanner.value("$")?;
+    Ok(scanner.expressions)
+}
+
+pub fn check_expressions(src: &str, keys: &[&str]) -> Result<Vec<(Expression, Span, String)>, String> {
+    Ok(find_expressions(src, keys)?
+        .into_iter()
+        .filter_map(|expression| match parse_metric_spanned(&expression.value) {
+            Ok(_) => None,
+            Err(err) => {
+                let span = expression.source_span(err.span);
+                Some((expression, span, err.summary().to_string()))
+            }
+        })
+        .collect())
+}
+
+pub fn unknown_functions(src: &str, keys: &[&str]) -> Result<Vec<(Expression, Span, String)>, String> {
+    Ok(find_expressions(src, keys)?
+        .into_iter()
+        .flat_map(|expression| {
+            let warnings = parse_recovering(&expression.value).warnings;
+            warnings.into_iter().map(move |warning| (expression.clone(), expression.source_span(warning.span), warning.message))
+        })
+        .collect())
+}
+
+pub fn format_expressions(src: &str, keys: &[&str]) -> Result<String, String> {
+    let mut output = String::new();
+    let mut errors = Vec::new();
+    let mut last = 0;
+    for expression in find_expressions(src, keys)? {
+        match parse_metric_spanned(&expression.value) {
+            Ok(metric) => {
+                output.push_str(&src[last..expression.span.start]);
+                output.push_str(&Value::String(compact_print(metric)).to_string());
+                last = expression.span.end;
+            }
+            Err(err) => {
+                let (line, col) = expression.source_span(err.span).line_col(src);
+                errors.push(format!("{}:{}: {}: {}", line, col, expression.path, err.summary()));
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors.join("\n"));
+    }
+    output.push_str(&src[last..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DASHBOARD: &str = r##"{
+  "title": "Blog",
+  "widgets": [
+    { "type": "markdown", "markdown": "# expression" },
+    {
+      "type": "graph",
+      "graph": {
+        "type": "expression",
+        "expression": "group(\n  host(22CXRB3pZmu, loadavg5),\n  avg(roleSlots('Blog:db', loadavg5))\n)"
+      }
+    },
+    { "type": "graph", "graph": { "type": "expression", "expression": "avg(host(22CXRB3pZmu))" } }
+  ],
+  "custom key": { "expression": "host(22CXRB3pZmu, loadavg5)" }
+}
+"##;
+
+    #[test]
+    fn test_find_expressions() {
+        let paths = find_expressions(DASHBOARD, DEFAULT_KEYS)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec!["$.widgets[1].graph.expression", "$.widgets[2].graph.expression", "$[\"custom key\"].expression"]
+        );
+        assert!(find_expressions("{\"expression\": }", DEFAULT_KEYS).is_err());
+    }
+
+    #[test]
+    fn test_check_expressions() {
+        let errors = check_expressions(DASHBOARD, DEFAULT_KEYS).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0.path, "$.widgets[2].graph.expression");
+        assert_eq!(errors[0].1.line_col(DASHBOARD), (12, 76));
+        assert_eq!(errors[0].2, "host expects 2 arguments but got 1");
+        let src = r#"{"expression": "alias(host(\"a\", '\u0062'),\n\tavg(x))"}"#;
+        let errors = check_expressions(src, DEFAULT_KEYS).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(&src[errors[0].1.start..], "avg(x))\"}");
+        let src = r#"{"expression": "group(alias(host(a, b), \"\ud83d\ude00\"), avg(x))"}"#;
+        let errors = check_expressions(src, DEFAULT_KEYS).unwrap();
+        assert_eq!(&src[errors[0].1.start..], "x))\"}");
+    }
+
+    #[test]
+    fn test_unknown_functions() {
+        assert!(unknown_functions(DASHBOARD, DEFAULT_KEYS).unwrap().is_empty());
+        let src = DASHBOARD.replace("avg(host(22CXRB3pZmu))", "avg(newFunction(host(22CXRB3pZmu, loadavg5)))");
+        let warnings = unknown_functions(&src, DEFAULT_KEYS).unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0.path, "$.widgets[2].graph.expression");
+        assert_eq!(warnings[0].1.line_col(&src), (12, 76));
+        assert_eq!(warnings[0].2, "unknown function: newFunction");
+    }
+
+    #[test]
+    fn test_format_expressions() {
+        let src = DASHBOARD.replace("avg(host(22CXRB3pZmu))", "avg(host( 22CXRB3pZmu , 'memory.*' ))");
+        let formatted = format_expressions(&src, DEFAULT_KEYS).unwrap();
+        assert_eq!(
+            formatted,
+            src.replace(
+                r#""group(\n  host(22CXRB3pZmu, loadavg5),\n  avg(roleSlots('Blog:db', loadavg5))\n)""#,
+                r#""group(host(22CXRB3pZmu, loadavg5), avg(roleSlots(Blog:db, loadavg5)))""#
+            ).replace("avg(host( 22CXRB3pZmu , 'memory.*' ))", "avg(host(22CXRB3pZmu, memory.*))")
+        );
+        let src = DASHBOARD.replace("\"host(22CXRB3pZmu, loadavg5)\"", "\"avg(\\\"x\\\")\"");
+        assert_eq!(
+            format_expressions(&src, DEFAULT_KEYS),
+            Err("12:76: $.widgets[2].graph.expression: host expects 2 arguments but got 1\n\
+                 14:38: $[\"custom key\"].expression: expected metrics: \"x\"".to_string())
+        );
+    }
+}
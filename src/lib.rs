@@ -1,10 +1,44 @@
+extern crate chrono;
+extern crate chrono_tz;
 extern crate pest;
 #[macro_use]
 extern crate pest_derive;
+#[cfg(test)]
+extern crate proptest;
+#[macro_use]
+extern crate serde_json;
+extern crate serde_yaml;
+#[cfg(feature = "mackerel")]
+extern crate ureq;
 
 use pest::Parser;
-use pest::iterators::Pair;
-use pest::inputs::Input;
+use pest::iterators::{Pair, Pairs};
+use pest::inputs::{Input, StrInput};
+
+#[cfg(test)]
+mod arbitrary;
+pub mod dialect;
+pub mod eval;
+pub mod explain;
+pub mod functions;
+pub mod graphite;
+pub mod hcl;
+pub mod json;
+pub mod macros;
+pub mod markdown;
+pub mod plot;
+pub mod promql;
+pub mod recover;
+pub mod references;
+pub mod rename;
+pub mod source;
+pub mod suggest;
+pub mod svg;
+pub mod template;
+pub mod tree;
+pub mod validate;
+#[cfg(feature = "mackerel")]
+pub mod mackerel;
 
 #[derive(Parser)]
 #[grammar = "metrics.pest"]
@@ -33,6 +67,15 @@ pub enum Metric {
     Group(Vec<Metric>),
     Stack(Box<Metric>),
     Alias(Box<Metric>, String),
+    Call { name: String, args: Vec<Argument> },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Argument {
+    Metric(Metric),
+    Factor(Factor),
+    Duration(Duration),
+    String(String),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -47,154 +90,420 @@ pub struct Percentage(String);
 #[derive(Debug, PartialEq, Clone)]
 pub struct Duration(String);
 
-macro_rules! next {
-    ($pairs:expr) => {
-        $pairs.next().unwrap()
+impl Metric {
+    pub fn function_name(&self) -> &str {
+        match *self {
+            Metric::Host(..) => "host",
+            Metric::Service(..) => "service",
+            Metric::Role(..) => "role",
+            Metric::RoleSlot(..) => "roleSlots",
+            Metric::Avg(..) => "avg",
+            Metric::Max(..) => "max",
+            Metric::Min(..) => "min",
+            Metric::Sum(..) => "sum",
+            Metric::Product(..) => "product",
+            Metric::Diff(..) => "diff",
+            Metric::Divide(..) => "divide",
+            Metric::Scale(..) => "scale",
+            Metric::Offset(..) => "offset",
+            Metric::Percentile(..) => "percentile",
+            Metric::TimeShift(..) => "timeShift",
+            Metric::MovingAverage(..) => "movingAverage",
+            Metric::LinearRegression(..) => "linearRegression",
+            Metric::TimeLeftForecast(..) => "timeLeftForecast",
+            Metric::Group(..) => "group",
+            Metric::Stack(..) => "stack",
+            Metric::Alias(..) => "alias",
+            Metric::Call { ref name, .. } => name,
+        }
     }
-}
 
-macro_rules! arg {
-    ($pairs:expr) => {
-        next!(next!($pairs).into_inner())
+    pub fn from_call(name: &str, args: Vec<Argument>) -> Option<Metric> {
+        let variant = match functions::function(name).and_then(|function| function.variant) {
+            Some(variant) => variant,
+            None => return Some(Metric::Call { name: name.to_string(), args }),
+        };
+        let mut args = Arguments(args.into_iter());
+        let metric = variant(&mut args)?;
+        match args.0.next() {
+            Some(_) => None,
+            None => Some(metric),
+        }
     }
-}
 
-macro_rules! arg_str {
-    ($pairs:expr) => {
-        arg!($pairs).as_str().to_string()
+    pub fn into_call(self) -> (String, Vec<Argument>) {
+        let name = self.function_name().to_string();
+        let args = match self {
+            Metric::Host(host_id, metric_name) => vec![Argument::String(host_id), Argument::String(metric_name)],
+            Metric::Service(service_name, metric_name) => {
+                vec![Argument::String(service_name), Argument::String(metric_name)]
+            }
+            Metric::Role(service_name, role_name, metric_name)
+            | Metric::RoleSlot(service_name, role_name, metric_name) => vec![
+                Argument::String(format!("{}:{}", service_name, role_name)),
+                Argument::String(metric_name),
+            ],
+            Metric::Avg(metric)
+            | Metric::Max(metric)
+            | Metric::Min(metric)
+            | Metric::Sum(metric)
+            | Metric::Product(metric)
+            | Metric::Stack(metric) => vec![Argument::Metric(*metric)],
+            Metric::Diff(metric1, metric2) | Metric::Divide(metric1, metric2) => {
+                vec![Argument::Metric(*metric1), Argument::Metric(*metric2)]
+            }
+            Metric::Scale(metric, factor) | Metric::Offset(metric, factor) => {
+                vec![Argument::Metric(*metric), Argument::Factor(factor)]
+            }
+            Metric::Percentile(metric, Percentage(percentage)) => {
+                vec![Argument::Metric(*metric), Argument::Factor(Factor::Double(percentage))]
+            }
+            Metric::TimeShift(metric, duration)
+            | Metric::MovingAverage(metric, duration)
+            | Metric::LinearRegression(metric, duration) => vec![Argument::Metric(*metric), Argument::Duration(duration)],
+            Metric::TimeLeftForecast(metric, duration, threshold) => vec![
+                Argument::Metric(*metric),
+                Argument::Duration(duration),
+                Argument::Factor(threshold),
+            ],
+            Metric::Group(metrics) => metrics.into_iter().map(Argument::Metric).collect(),
+            Metric::Alias(metric, display_name) => vec![Argument::Metric(*metric), Argument::String(display_name)],
+            Metric::Call { args, .. } => args,
+        };
+        (name, args)
     }
-}
 
-pub fn parse_metric(src: &str) -> Result<Metric, String> {
-    let mut pairs = MetricParser::parse_str(Rule::whole_metrics, src).map_err(|e| format!("{}", e))?;
-    convert_metrics(next!(next!(pairs).into_inner()))
+    pub fn unknown_functions(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        collect_unknown_functions(self.clone(), &mut names);
+        names
+    }
 }
 
-fn convert_metrics<I: Input>(pair: Pair<Rule, I>) -> Result<Metric, String> {
-    match pair.as_rule() {
-        Rule::host_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Host(arg_str!(inner), arg_str!(inner)))
-        }
-        Rule::service_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Service(arg_str!(inner), arg_str!(inner)))
-        }
-        Rule::role_metric => {
-            let mut inner = pair.into_inner();
-            let mut role_full_name = arg!(inner).into_inner();
-            let service_name = next!(role_full_name).as_str().to_string();
-            let role_name = next!(role_full_name).as_str().to_string();
-            Ok(Metric::Role(service_name, role_name, arg_str!(inner)))
-        }
-        Rule::role_slot_metric => {
-            let mut inner = pair.into_inner();
-            let mut role_full_name = arg!(inner).into_inner();
-            let service_name = next!(role_full_name).as_str().to_string();
-            let role_name = next!(role_full_name).as_str().to_string();
-            Ok(Metric::RoleSlot(service_name, role_name, arg_str!(inner)))
-        }
-        Rule::avg_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Avg(Box::new(convert_metrics(next!(inner))?)))
-        }
-        Rule::max_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Max(Box::new(convert_metrics(next!(inner))?)))
-        }
-        Rule::min_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Min(Box::new(convert_metrics(next!(inner))?)))
-        }
-        Rule::sum_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Sum(Box::new(convert_metrics(next!(inner))?)))
+fn collect_unknown_functions(metric: Metric, names: &mut Vec<String>) {
+    let (name, args) = metric.into_call();
+    if functions::function(&name).is_none() {
+        names.push(name);
+    }
+    for arg in args {
+        if let Argument::Metric(metric) = arg {
+            collect_unknown_functions(metric, names);
         }
-        Rule::product_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Product(Box::new(convert_metrics(next!(inner))?)))
+    }
+}
+
+pub struct Arguments(std::vec::IntoIter<Argument>);
+
+impl Arguments {
+    pub fn metric(&mut self) -> Option<Box<Metric>> {
+        self.0.next()?.into_metric()
+    }
+
+    pub fn metrics(&mut self) -> Option<Vec<Metric>> {
+        self.0.by_ref().map(|arg| arg.into_metric().map(|metric| *metric)).collect()
+    }
+
+    pub fn factor(&mut self) -> Option<Factor> {
+        self.0.next()?.into_factor()
+    }
+
+    pub fn percentage(&mut self) -> Option<Percentage> {
+        match self.factor()? {
+            Factor::Double(percentage) => Some(Percentage(percentage)),
+            Factor::Fraction(..) => None,
         }
-        Rule::diff_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Diff(
-                Box::new(convert_metrics(next!(inner))?),
-                Box::new(convert_metrics(next!(inner))?),
-            ))
+    }
+
+    pub fn duration(&mut self) -> Option<Duration> {
+        self.0.next()?.into_duration()
+    }
+
+    pub fn string(&mut self) -> Option<String> {
+        self.0.next()?.into_string()
+    }
+
+    pub fn role(&mut self) -> Option<(String, String)> {
+        let role_full_name = self.string()?;
+        let (service_name, role_name) = role_full_name.split_at(role_full_name.find(':')?);
+        Some((service_name.to_string(), role_name[1..].to_string()))
+    }
+}
+
+impl Argument {
+    fn into_metric(self) -> Option<Box<Metric>> {
+        match self {
+            Argument::Metric(metric) => Some(Box::new(metric)),
+            _ => None,
         }
-        Rule::divide_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Divide(
-                Box::new(convert_metrics(next!(inner))?),
-                Box::new(convert_metrics(next!(inner))?),
-            ))
+    }
+
+    fn into_factor(self) -> Option<Factor> {
+        match self {
+            Argument::Factor(factor) => Some(factor),
+            _ => None,
         }
-        Rule::scale_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Scale(
-                Box::new(convert_metrics(next!(inner))?),
-                convert_factor(arg!(inner))?,
-            ))
+    }
+
+    fn into_duration(self) -> Option<Duration> {
+        match self {
+            Argument::Duration(duration) => Some(duration),
+            _ => None,
         }
-        Rule::offset_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Offset(
-                Box::new(convert_metrics(next!(inner))?),
-                convert_factor(arg!(inner))?,
-            ))
+    }
+
+    fn into_string(self) -> Option<String> {
+        match self {
+            Argument::String(s) => Some(s),
+            _ => None,
         }
-        Rule::percentile_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Percentile(
-                Box::new(convert_metrics(next!(inner))?),
-                convert_percentage(next!(inner))?,
-            ))
+    }
+}
+
+impl Factor {
+    pub fn value(&self) -> f64 {
+        match *self {
+            Factor::Double(ref s) => s.parse().unwrap_or(f64::NAN),
+            Factor::Fraction(ref nume, ref deno) => {
+                nume.parse::<f64>().unwrap_or(f64::NAN) / deno.parse::<f64>().unwrap_or(f64::NAN)
+            }
         }
-        Rule::time_shift_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::TimeShift(
-                Box::new(convert_metrics(next!(inner))?),
-                convert_duration(arg!(inner))?,
-            ))
+    }
+}
+
+impl Percentage {
+    pub fn value(&self) -> f64 {
+        self.0.parse().unwrap_or(f64::NAN)
+    }
+}
+
+impl std::str::FromStr for Duration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Duration, String> {
+        match MetricParser::parse_str(Rule::duration_inner, s).map(|mut pairs| pairs.next()) {
+            Ok(Some(ref pair)) if pair.as_str() == s => Ok(Duration(s.to_string())),
+            _ => Err(format!("invalid duration: {}", s)),
         }
-        Rule::moving_average_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::MovingAverage(
-                Box::new(convert_metrics(next!(inner))?),
-                convert_duration(arg!(inner))?,
-            ))
+    }
+}
+
+impl Duration {
+    pub fn seconds(&self) -> i64 {
+        let unit_start = self.0.find(|c: char| !c.is_ascii_digit()).unwrap_or(self.0.len());
+        let count = self.0[..unit_start].parse::<i64>().unwrap_or(0);
+        count * match &self.0[unit_start..] {
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            "mo" => 30 * 24 * 60 * 60,
+            "y" => 365 * 24 * 60 * 60,
+            _ => 0,
         }
-        Rule::linear_regression_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::LinearRegression(
-                Box::new(convert_metrics(next!(inner))?),
-                convert_duration(arg!(inner))?,
-            ))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn line_col(&self, src: &str) -> (usize, usize) {
+        let before = &src[..self.start];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        (line, col)
+    }
+}
+
+macro_rules! next {
+    ($pairs:expr) => {
+        $pairs.next().ok_or_else(|| "unexpected end of input".to_string())?
+    }
+}
+
+/// The deepest nesting accepted by the parser. A debug build overflows the
+/// 2 MiB stack of a spawned thread between 320 and 384 levels, so 256 keeps
+/// parsing, printing and recovery within the default thread stack.
+pub const MAX_DEPTH: usize = 256;
+
+fn check_depth(src: &str) -> Result<(), ParseError> {
+    let mut depth = 0;
+    let mut chars = src.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '(' => {
+                depth += 1;
+                if depth > MAX_DEPTH {
+                    let message = format!("nested deeper than {} levels", MAX_DEPTH);
+                    return Err(ParseError { span: Span { start: i, end: i + 1 }, message });
+                }
+            }
+            ')' => depth -= depth.min(1),
+            '\'' | '"' if src[i + 1..].contains(c) => {
+                chars.by_ref().find(|&(_, d)| d == c);
+            }
+            _ => {}
         }
-        Rule::time_left_forecast_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::TimeLeftForecast(
-                Box::new(convert_metrics(next!(inner))?),
-                convert_duration(arg!(inner))?,
-                convert_factor(arg!(inner))?,
-            ))
+    }
+    Ok(())
+}
+
+/// Parses an expression. Expressions nested deeper than [`MAX_DEPTH`] levels
+/// are rejected before parsing.
+pub fn parse_metric(src: &str) -> Result<Metric, String> {
+    parse_metric_pairs(src).map(|(metric, _)| metric)
+}
+
+fn parse_metric_pairs<'a>(src: &'a str) -> Result<(Metric, Pairs<Rule, StrInput<'a>>), String> {
+    check_depth(src).map_err(|err| err.message)?;
+    let located = |err: ParseError| {
+        let (line, col) = err.span.line_col(src);
+        format!("{}:{}: {}", line, col, err.message)
+    };
+    let pairs = MetricParser::parse_str(Rule::whole_metrics, src).map_err(|e| match recover::diagnose(src) {
+        Some(err) => located(err),
+        None => format!("{}", e),
+    })?;
+    let metric = convert_whole_metrics(pairs.clone()).map_err(located)?;
+    Ok((metric, pairs))
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn summary(&self) -> &str {
+        self.message.rsplit("= ").next().unwrap_or(&self.message)
+    }
+}
+
+pub fn parse_metric_with_dialect(src: &str, dialect: &dialect::Dialect) -> Result<Metric, String> {
+    let metric = parse_metric(src)?;
+    match dialect::check(src, dialect)?.into_iter().next() {
+        Some(err) => {
+            let (line, col) = err.span.line_col(src);
+            Err(format!("{}:{}: {}", line, col, err.message))
         }
-        Rule::group_metric => {
-            let mut metrics = Vec::new();
-            for r in pair.into_inner() {
-                metrics.push(convert_metrics(r)?);
-            }
-            Ok(Metric::Group(metrics))
+        None => Ok(metric),
+    }
+}
+
+pub fn parse_metric_spanned(src: &str) -> Result<Metric, ParseError> {
+    check_depth(src)?;
+    parse_metric_pest(src).map_err(|err| recover::diagnose(src).unwrap_or(err))
+}
+
+fn parse_metric_pest(src: &str) -> Result<Metric, ParseError> {
+    match MetricParser::parse_str(Rule::whole_metrics, src) {
+        Ok(pairs) => convert_whole_metrics(pairs),
+        Err(err) => {
+            let span = match err {
+                pest::Error::ParsingError { ref pos, .. } | pest::Error::CustomErrorPos { ref pos, .. } => {
+                    Span { start: pos.pos(), end: pos.pos() }
+                }
+                pest::Error::CustomErrorSpan { ref span, .. } => Span { start: span.start(), end: span.end() },
+            };
+            Err(ParseError { span, message: format!("{}", err) })
         }
-        Rule::stack_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Stack(Box::new(convert_metrics(next!(inner))?)))
+    }
+}
+
+fn span_of<I: Input>(pair: &Pair<Rule, I>) -> Span {
+    let span = pair.clone().into_span();
+    Span { start: span.start(), end: span.end() }
+}
+
+fn convert_whole_metrics<I: Input>(mut pairs: Pairs<Rule, I>) -> Result<Metric, ParseError> {
+    match pairs.next().and_then(|pair| pair.into_inner().next()) {
+        Some(pair) => convert_metrics(pair),
+        None => Err(ParseError { span: Span { start: 0, end: 0 }, message: "unexpected end of input".to_string() }),
+    }
+}
+
+fn convert_metrics<I: Input>(pair: Pair<Rule, I>) -> Result<Metric, ParseError> {
+    let span = span_of(&pair);
+    let error = |span, message| ParseError { span, message };
+    if pair.as_rule() != Rule::metrics {
+        return Err(error(span, format!("invalid metrics: {:?}", pair.as_rule())));
+    }
+    let mut inner = pair.into_inner();
+    let name = inner.next().ok_or_else(|| error(span, "unexpected end of input".to_string()))?;
+    let args = inner.collect::<Vec<_>>();
+    match functions::function(name.as_str()) {
+        Some(function) => {
+            function.check_arity(args.len()).map_err(|message| error(span_of(&name), message))?;
+            let args = args
+                .into_iter()
+                .enumerate()
+                .map(|(i, arg)| match function.kind(i) {
+                    Some(kind) => convert_typed_argument(arg, kind),
+                    None => convert_argument(arg),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Metric::from_call(function.name, args)
+                .ok_or_else(|| error(span, format!("invalid arguments to {}", function.name)))
         }
-        Rule::alias_metric => {
-            let mut inner = pair.into_inner();
-            Ok(Metric::Alias(Box::new(convert_metrics(next!(inner))?), arg_str!(inner)))
+        None => Ok(Metric::Call {
+            name: name.as_str().to_string(),
+            args: args.into_iter().map(convert_argument).collect::<Result<_, _>>()?,
+        }),
+    }
+}
+
+fn argument_value<I: Input>(pair: Pair<Rule, I>) -> Result<Pair<Rule, I>, ParseError> {
+    let span = span_of(&pair);
+    pair.into_inner()
+        .next()
+        .ok_or_else(|| ParseError { span, message: "unexpected end of input".to_string() })
+}
+
+fn convert_argument<I: Input>(pair: Pair<Rule, I>) -> Result<Argument, ParseError> {
+    let pair = argument_value(pair)?;
+    let span = span_of(&pair);
+    if pair.as_rule() == Rule::metrics {
+        return Ok(Argument::Metric(convert_metrics(pair)?));
+    }
+    let value = (|| match pair.as_rule() {
+        Rule::factor => Ok(Argument::Factor(convert_factor(next!(pair.into_inner()))?)),
+        Rule::duration => Ok(Argument::Duration(convert_duration(next!(pair.into_inner()))?)),
+        Rule::display_name => Ok(Argument::String(next!(pair.into_inner()).as_str().to_string())),
+        r => Err(format!("invalid argument: {:?}", r)),
+    })();
+    value.map_err(|message| ParseError { span, message })
+}
+
+fn convert_typed_argument<I: Input>(pair: Pair<Rule, I>, kind: functions::Kind) -> Result<Argument, ParseError> {
+    let pair = argument_value(pair)?;
+    let span = span_of(&pair);
+    match (kind, pair.as_rule()) {
+        (functions::Kind::Metrics, Rule::metrics) => Ok(Argument::Metric(convert_metrics(pair)?)),
+        (_, Rule::metrics) => Err(ParseError { span, message: kind.invalid(pair.as_str()) }),
+        _ => convert_literal(kind, pair.as_str()).map_err(|message| ParseError { span, message }),
+    }
+}
+
+pub(crate) fn convert_literal(kind: functions::Kind, text: &str) -> Result<Argument, String> {
+    let literal = match kind.rule().map(|rule| MetricParser::parse_str(rule, text).map(|mut pairs| pairs.next())) {
+        Some(Ok(Some(literal))) if literal.as_str() == text => literal,
+        _ => return Err(kind.invalid(text)),
+    };
+    match kind {
+        functions::Kind::RoleFullName => {
+            let mut role_full_name = next!(literal.into_inner()).into_inner();
+            let service_name = next!(role_full_name);
+            let role_name = next!(role_full_name);
+            Ok(Argument::String(format!("{}:{}", service_name.as_str(), role_name.as_str())))
         }
-        Rule::metrics => convert_metrics(next!(pair.into_inner())),
-        _ => unreachable!(),
+        functions::Kind::Factor => Ok(Argument::Factor(convert_factor(next!(literal.into_inner()))?)),
+        functions::Kind::Percentage => Ok(Argument::Factor(Factor::Double(literal.as_str().to_string()))),
+        functions::Kind::Duration => Ok(Argument::Duration(convert_duration(next!(literal.into_inner()))?)),
+        _ => Ok(Argument::String(next!(literal.into_inner()).as_str().to_string())),
     }
 }
 
@@ -212,17 +521,10 @@ fn convert_factor<I: Input>(pair: Pair<Rule, I>) -> Result<Factor, String> {
     }
 }
 
-fn convert_percentage<I: Input>(pair: Pair<Rule, I>) -> Result<Percentage, String> {
-    match pair.as_rule() {
-        Rule::double => Ok(Percentage(pair.as_str().to_string())),
-        r => Err(format!("invalid percentage: {:?}", r)),
-    }
-}
-
 fn convert_duration<I: Input>(pair: Pair<Rule, I>) -> Result<Duration, String> {
     match pair.as_rule() {
         Rule::duration_inner => Ok(Duration(pair.as_str().to_string())),
-        r => Err(format!("invalid percentage: {:?}", r)),
+        r => Err(format!("invalid duration: {:?}", r)),
     }
 }
 
@@ -231,206 +533,46 @@ pub fn pretty_print(metric: Metric) -> String {
 }
 
 fn calc_depth(metric: Metric) -> u64 {
-    match metric {
-        Metric::Avg(metric) => 1 + calc_depth(*metric),
-        Metric::Max(metric) => 1 + calc_depth(*metric),
-        Metric::Min(metric) => 1 + calc_depth(*metric),
-        Metric::Sum(metric) => 1 + calc_depth(*metric),
-        Metric::Product(metric) => 1 + calc_depth(*metric),
-        Metric::Diff(metric1, metric2) => 1 + vec![calc_depth(*metric1), calc_depth(*metric2)].iter().max().unwrap(),
-        Metric::Divide(metric1, metric2) => 1 + vec![calc_depth(*metric1), calc_depth(*metric2)].iter().max().unwrap(),
-        Metric::Scale(metric, _) => 1 + calc_depth(*metric),
-        Metric::Offset(metric, _) => 1 + calc_depth(*metric),
-        Metric::Percentile(metric, _) => 1 + calc_depth(*metric),
-        Metric::TimeShift(metric, _) => 1 + calc_depth(*metric),
-        Metric::MovingAverage(metric, _) => 1 + calc_depth(*metric),
-        Metric::LinearRegression(metric, _) => 1 + calc_depth(*metric),
-        Metric::TimeLeftForecast(metric, _, _) => 1 + calc_depth(*metric),
-        Metric::Group(metrics) => 1 + metrics.iter().map(|metric| calc_depth(metric.clone())).max().unwrap(),
-        Metric::Stack(metric) => 1 + calc_depth(*metric),
-        Metric::Alias(metric, _) => 1 + calc_depth(*metric),
-        _ => 1,
-    }
+    let (_, args) = metric.into_call();
+    1 + args
+        .into_iter()
+        .map(|arg| match arg {
+            Argument::Metric(metric) => calc_depth(metric),
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
 }
 
 fn pretty_print_inner(metric: Metric, depth: u64, indent: usize) -> String {
     let indent_str = " ".repeat(indent * 2);
-    let metric_str = match metric {
-        Metric::Host(host_id, metric_name) => format!("host({}, {})", host_id, metric_name),
-        Metric::Service(service_name, metric_name) => format!("service({}, {})", service_name, metric_name),
-        Metric::Role(service_name, role_name, metric_name) => format!("role({}:{}, {})", service_name, role_name, metric_name),
-        Metric::RoleSlot(service_name, role_name, metric_name) => format!("roleSlots({}:{}, {})", service_name, role_name, metric_name),
-        Metric::Avg(metric) => if depth <= 2 {
-            format!("avg({})", pretty_print_inner(*metric, depth - 1, 0))
-        } else {
-            format!("avg(\n{}\n{})", pretty_print_inner(*metric, depth - 1, indent + 1), indent_str)
-        },
-        Metric::Max(metric) => if depth <= 2 {
-            format!("max({})", pretty_print_inner(*metric, depth - 1, 0))
-        } else {
-            format!("max(\n{}\n{})", pretty_print_inner(*metric, depth - 1, indent + 1), indent_str)
-        },
-        Metric::Min(metric) => if depth <= 2 {
-            format!("min({})", pretty_print_inner(*metric, depth - 1, 0))
-        } else {
-            format!("min(\n{}\n{})", pretty_print_inner(*metric, depth - 1, indent + 1), indent_str)
-        },
-        Metric::Sum(metric) => if depth <= 2 {
-            format!("sum({})", pretty_print_inner(*metric, depth - 1, 0))
-        } else {
-            format!("sum(\n{}\n{})", pretty_print_inner(*metric, depth - 1, indent + 1), indent_str)
-        },
-        Metric::Product(metric) => if depth <= 2 {
-            format!("product({})", pretty_print_inner(*metric, depth - 1, 0))
-        } else {
-            format!(
-                "product(\n{}\n{})",
-                pretty_print_inner(*metric, depth - 1, indent + 1),
-                indent_str
-            )
-        },
-        Metric::Diff(metric1, metric2) => format!(
-            "diff(\n{},\n{}\n{})",
-            pretty_print_inner(*metric1, depth - 1, indent + 1),
-            pretty_print_inner(*metric2, depth - 1, indent + 1),
-            indent_str
-        ),
-        Metric::Divide(metric1, metric2) => format!(
-            "divide(\n{},\n{}\n{})",
-            pretty_print_inner(*metric1, depth - 1, indent + 1),
-            pretty_print_inner(*metric2, depth - 1, indent + 1),
+    let function = functions::function(metric.function_name());
+    let inline = function.is_none_or(|function| function.inline);
+    let (name, args) = metric.into_call();
+    let args = args.into_iter().enumerate().map(|(i, arg)| (arg, function.and_then(|function| function.kind(i))));
+    let metric_str = if inline && depth <= 2 {
+        format!(
+            "{}({})",
+            name,
+            args.map(|(arg, kind)| match arg {
+                Argument::Metric(metric) => pretty_print_inner(metric, depth - 1, 0),
+                arg => pretty_print_argument(arg, kind),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+        )
+    } else {
+        format!(
+            "{}(\n{}\n{})",
+            name,
+            args.map(|(arg, kind)| match arg {
+                Argument::Metric(metric) => pretty_print_inner(metric, depth - 1, indent + 1),
+                arg => format!("  {}{}", indent_str, pretty_print_argument(arg, kind)),
+            })
+            .collect::<Vec<_>>()
+            .join(",\n"),
             indent_str
-        ),
-        Metric::Scale(metric, factor) => if depth <= 2 {
-            format!(
-                "scale({}, {})",
-                pretty_print_inner(*metric, depth - 1, 0),
-                pretty_print_factor(factor)
-            )
-        } else {
-            format!(
-                "scale(\n{},\n  {}{}\n{})",
-                pretty_print_inner(*metric, depth - 1, indent + 1),
-                indent_str,
-                pretty_print_factor(factor),
-                indent_str
-            )
-        },
-        Metric::Offset(metric, factor) => if depth <= 2 {
-            format!(
-                "offset({}, {})",
-                pretty_print_inner(*metric, depth - 1, 0),
-                pretty_print_factor(factor)
-            )
-        } else {
-            format!(
-                "offset(\n{},\n  {}{}\n{})",
-                pretty_print_inner(*metric, depth - 1, indent + 1),
-                indent_str,
-                pretty_print_factor(factor),
-                indent_str
-            )
-        },
-        Metric::Percentile(metric, percentage) => if depth <= 2 {
-            format!(
-                "percentile({}, {})",
-                pretty_print_inner(*metric, depth - 1, 0),
-                pretty_print_percentage(percentage)
-            )
-        } else {
-            format!(
-                "percentile(\n{},\n  {}{}\n{})",
-                pretty_print_inner(*metric, depth - 1, indent + 1),
-                indent_str,
-                pretty_print_percentage(percentage),
-                indent_str
-            )
-        },
-        Metric::TimeShift(metric, duration) => if depth <= 2 {
-            format!(
-                "timeShift({}, {})",
-                pretty_print_inner(*metric, depth - 1, 0),
-                pretty_print_duration(duration),
-            )
-        } else {
-            format!(
-                "timeShift(\n{},\n  {}{}\n{})",
-                pretty_print_inner(*metric, depth - 1, indent + 1),
-                indent_str,
-                pretty_print_duration(duration),
-                indent_str
-            )
-        },
-        Metric::MovingAverage(metric, duration) => if depth <= 2 {
-            format!(
-                "movingAverage({}, {})",
-                pretty_print_inner(*metric, depth - 1, 0),
-                pretty_print_duration(duration),
-            )
-        } else {
-            format!(
-                "movingAverage(\n{},\n  {}{}\n{})",
-                pretty_print_inner(*metric, depth - 1, indent + 1),
-                indent_str,
-                pretty_print_duration(duration),
-                indent_str
-            )
-        },
-        Metric::LinearRegression(metric, duration) => if depth <= 2 {
-            format!(
-                "linearRegression({}, {})",
-                pretty_print_inner(*metric, depth - 1, 0),
-                pretty_print_duration(duration),
-            )
-        } else {
-            format!(
-                "linearRegression(\n{},\n  {}{}\n{})",
-                pretty_print_inner(*metric, depth - 1, indent + 1),
-                indent_str,
-                pretty_print_duration(duration),
-                indent_str
-            )
-        },
-        Metric::TimeLeftForecast(metric, duration, threshold) => format!(
-            "timeLeftForecast(\n{},\n  {}{},\n  {}{}\n{})",
-            pretty_print_inner(*metric, depth - 1, indent + 1),
-            indent_str,
-            pretty_print_duration(duration),
-            indent_str,
-            pretty_print_factor(threshold),
-            indent_str
-        ),
-        Metric::Group(metrics) => format!(
-            "group(\n{}\n{})",
-            metrics
-                .iter()
-                .map(|metric| pretty_print_inner(metric.clone(), depth - 1, indent + 1))
-                .collect::<Vec<_>>()
-                .join(",\n"),
-            indent_str
-        ),
-        Metric::Stack(metric) => if depth <= 2 {
-            format!("stack({})", pretty_print_inner(*metric, depth - 1, 0))
-        } else {
-            format!(
-                "stack(\n{}\n{})",
-                pretty_print_inner(*metric, depth - 1, indent + 1),
-                indent_str
-            )
-        },
-        Metric::Alias(metric, display_name) => format!(
-            "alias(\n{},\n  {}{}\n{})",
-            pretty_print_inner(*metric, depth - 1, indent + 1),
-            indent_str,
-            if display_name.contains("'") {
-                format!("\"{}\"", display_name)
-            } else if vec![" ", "\t", "\n", "\r", "'", "\"", "(", ",", ")"].iter().any(|c| display_name.contains(c)) {
-                format!("'{}'", display_name)
-            } else {
-                format!("{}", display_name)
-            },
-            indent_str
-        ),
+        )
     };
     format!("{}{}", indent_str, metric_str)
 }
@@ -442,18 +584,46 @@ fn pretty_print_factor(factor: Factor) -> String {
     }
 }
 
-fn pretty_print_percentage(percentage: Percentage) -> String {
-    match percentage {
-        Percentage(s) => s,
+fn pretty_print_display_name(display_name: String) -> String {
+    if display_name.is_empty() {
+        "''".to_string()
+    } else if display_name.contains("'") {
+        format!("\"{}\"", display_name)
+    } else if [" ", "\t", "\n", "\r", "'", "\"", "(", ",", ")"].iter().any(|c| display_name.contains(c)) {
+        format!("'{}'", display_name)
+    } else {
+        display_name
     }
 }
 
-fn pretty_print_duration(duration: Duration) -> String {
-    match duration {
-        Duration(s) => s,
+fn pretty_print_argument(arg: Argument, kind: Option<functions::Kind>) -> String {
+    match arg {
+        Argument::Metric(metric) => compact_print(metric),
+        Argument::Factor(factor) => pretty_print_factor(factor),
+        Argument::Duration(Duration(s)) => s,
+        Argument::String(s) => match kind {
+            Some(functions::Kind::DisplayName) => pretty_print_display_name(s),
+            Some(_) => s,
+            None if recover::matches(Rule::factor, &s) || recover::matches(Rule::duration, &s) => format!("'{}'", s),
+            None => pretty_print_display_name(s),
+        },
     }
 }
 
+pub fn compact_print(metric: Metric) -> String {
+    let function = functions::function(metric.function_name());
+    let (name, args) = metric.into_call();
+    format!(
+        "{}({})",
+        name,
+        args.into_iter()
+            .enumerate()
+            .map(|(i, arg)| pretty_print_argument(arg, function.and_then(|function| function.kind(i))))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -717,6 +887,45 @@ mod tests {
                 ),
                 "alias(\n  service(Blog, foo.bar),\n  Blog\n)",
             ),
+            (
+                "alias(service(Blog, foo.bar), \"\")",
+                Metric::Alias(
+                    Box::new(Metric::Service("Blog".to_string(), "foo.bar".to_string())),
+                    "".to_string(),
+                ),
+                "alias(\n  service(Blog, foo.bar),\n  ''\n)",
+            ),
+            (
+                "newFunction( host(22CXRB3pZmu, loadavg5), 1/2, -3, 5m, '5', 'a b', Blog:db )",
+                Metric::Call {
+                    name: "newFunction".to_string(),
+                    args: vec![
+                        Argument::Metric(Metric::Host("22CXRB3pZmu".to_string(), "loadavg5".to_string())),
+                        Argument::Factor(Factor::Fraction("1".to_string(), "2".to_string())),
+                        Argument::Factor(Factor::Double("-3".to_string())),
+                        Argument::Duration(Duration("5m".to_string())),
+                        Argument::String("5".to_string()),
+                        Argument::String("a b".to_string()),
+                        Argument::String("Blog:db".to_string()),
+                    ],
+                },
+                "newFunction(host(22CXRB3pZmu, loadavg5), 1/2, -3, 5m, '5', 'a b', Blog:db)",
+            ),
+            (
+                "nested(avg(host(22CXRB3pZmu, loadavg5)), 1d)",
+                Metric::Call {
+                    name: "nested".to_string(),
+                    args: vec![
+                        Argument::Metric(Metric::Avg(Box::new(Metric::Host(
+                            "22CXRB3pZmu".to_string(),
+                            "loadavg5".to_string(),
+                        )))),
+                        Argument::Duration(Duration("1d".to_string())),
+                    ],
+                },
+                "nested(\n  avg(host(22CXRB3pZmu, loadavg5)),\n  1d\n)",
+            ),
+            ("now()", Metric::Call { name: "now".to_string(), args: vec![] }, "now()"),
         ]
     }
 
@@ -728,6 +937,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_metric_spanned() {
+        assert!(parse_metric_spanned("avg(host(22CXRB3pZmu, loadavg5))").is_ok());
+        let src = format!("{}host(22CXRB3pZmu, loadavg5){}", "avg(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(parse_metric_spanned(&src[4..src.len() - 1]).is_ok());
+        let err = parse_metric_spanned(&src).unwrap_err();
+        assert_eq!((err.span.start, err.summary()), (4 * MAX_DEPTH + 4, "nested deeper than 256 levels"));
+        assert!(parse_metric(&format!("{}alias(host(a, b), '(('){}", "avg(".repeat(30), ")".repeat(30))).is_ok());
+        let err = parse_metric_spanned("group(host(22CXRB3pZmu, loadavg5), avg(host(22CXRB3pZmu)))").unwrap_err();
+        assert_eq!(err.span, Span { start: 39, end: 43 });
+        assert_eq!(err.summary(), "host expects 2 arguments but got 1");
+        let src = "timeShift(host(22CXRB3pZmu, loadavg5), 5min)";
+        let err = parse_metric_spanned(src).unwrap_err();
+        assert_eq!((err.span.start, err.message.as_str()), (39, "invalid duration: 5min (did you mean 5m?)"));
+        assert_eq!(parse_metric(src), Err("1:40: invalid duration: 5min (did you mean 5m?)".to_string()));
+        for &(src, start, message) in &[
+            ("movingAvg(host(22CXRB3pZmu, loadavg5), 5m)", 0, "unknown function: movingAvg (did you mean movingAverage?)"),
+            ("avg(roleSlot(Blog:db, loadavg5))", 4, "unknown function: roleSlot (did you mean roleSlots?)"),
+            ("timeshift(host(22CXRB3pZmu, loadavg5), 1d)", 0, "unknown function: timeshift (did you mean timeShift?)"),
+        ] {
+            assert!(parse_metric_spanned(src).is_ok());
+            let warnings = recover::parse_recovering(src).warnings;
+            assert_eq!(warnings.iter().map(|err| (err.span.start, err.message.as_str())).collect::<Vec<_>>(), vec![(start, message)]);
+        }
+    }
+
+    #[test]
+    fn test_unknown_functions() {
+        let metric = parse_metric("group(newFunction(host(22CXRB3pZmu, loadavg5)), avg(otherFunction()))").unwrap();
+        assert_eq!(metric.unknown_functions(), vec!["newFunction", "otherFunction"]);
+        assert!(parse_metric("avg(host(22CXRB3pZmu, loadavg5))").unwrap().unknown_functions().is_empty());
+        let host = Metric::Host("22CXRB3pZmu".to_string(), "loadavg5".to_string());
+        let call = Metric::Call { name: "stack".to_string(), args: vec![Argument::Metric(host)] };
+        assert!(call.unknown_functions().is_empty());
+        assert_eq!(compact_print(call), "stack(host(22CXRB3pZmu, loadavg5))");
+        assert!(parse_metric("avg(host(22CXRB3pZmu))").is_err());
+        assert!(parse_metric("hosts(22CXRB3pZmu)").is_ok());
+    }
+
+    #[test]
+    fn test_compact_print() {
+        for (_, metric, pretty) in test_cases() {
+            let got = compact_print(metric.clone());
+            assert!(!got.contains('\n'));
+            assert_eq!(parse_metric(got.as_ref()), Ok(metric));
+            assert_eq!(pretty_print(parse_metric(got.as_ref()).unwrap()), pretty);
+        }
+    }
+
     #[test]
     fn test_pretty_print() {
         for (_, metric, pretty) in test_cases() {
@@ -0,0 +1,102 @@
+use std::time;
+
+use serde_json::{self, Value};
+use ureq;
+
+use source::{field, hosts_from_json, points_from_json, services_from_json, strings, Host, MetricSource, Point, Service};
+
+pub const DEFAULT_BASE_URL: &str = "https://api.mackerelio.com";
+
+pub struct Client {
+    base_url: String,
+    api_key: String,
+    agent: ureq::Agent,
+}
+
+impl Client {
+    pub fn new(api_key: &str) -> Client {
+        Client::with_base_url(DEFAULT_BASE_URL, api_key)
+    }
+
+    pub fn with_base_url(base_url: &str, api_key: &str) -> Client {
+        Client {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(time::Duration::from_secs(30)).build(),
+        }
+    }
+
+    pub fn from_env() -> Result<Client, String> {
+        let api_key = std::env::var("MACKEREL_APIKEY").map_err(|_| "MACKEREL_APIKEY is not set".to_string())?;
+        let base_url = std::env::var("MACKEREL_APIBASE").unwrap_or_else(|_| DEFAULT_BASE_URL.to_string());
+        Ok(Client::with_base_url(&base_url, &api_key))
+    }
+
+    fn get(&self, path: &str, query: &[(&str, &str)]) -> Result<Value, String> {
+        let url = format!("{}{}", self.base_url, path);
+        let mut request = self.agent.get(&url).set("X-Api-Key", &self.api_key);
+        for &(key, value) in query {
+            request = request.query(key, value);
+        }
+        match request.call() {
+            Ok(response) => serde_json::from_reader(response.into_reader()).map_err(|e| format!("{}: {}", url, e)),
+            Err(ureq::Error::Status(status, response)) => Err(format!(
+                "{}: {} {}",
+                url,
+                status,
+                response.into_string().unwrap_or_default()
+            )),
+            Err(e) => Err(format!("{}: {}", url, e)),
+        }
+    }
+}
+
+fn path_segment(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl MetricSource for Client {
+    fn services(&self) -> Result<Vec<Service>, String> {
+        services_from_json(&self.get("/api/v0/services", &[])?)
+    }
+
+    fn hosts(&self, service: Option<&str>, role: Option<&str>) -> Result<Vec<Host>, String> {
+        let mut query = Vec::new();
+        if let Some(service) = service {
+            query.push(("service", service));
+        }
+        if let Some(role) = role {
+            query.push(("role", role));
+        }
+        hosts_from_json(&self.get("/api/v0/hosts", &query)?)
+    }
+
+    fn host_metric_names(&self, host_id: &str) -> Result<Vec<String>, String> {
+        strings(&self.get(&format!("/api/v0/hosts/{}/metric-names", path_segment(host_id)), &[])?, "names")
+    }
+
+    fn service_metric_names(&self, service_name: &str) -> Result<Vec<String>, String> {
+        strings(&self.get(&format!("/api/v0/services/{}/metric-names", path_segment(service_name)), &[])?, "names")
+    }
+
+    fn host_metric_values(&self, host_id: &str, metric_name: &str, from: i64, to: i64) -> Result<Vec<Point>, String> {
+        let (from, to) = (from.to_string(), to.to_string());
+        points_from_json(field(&self.get(
+            &format!("/api/v0/hosts/{}/metrics", path_segment(host_id)),
+            &[("name", metric_name), ("from", &from), ("to", &to)],
+        )?, "metrics")?)
+    }
+
+    fn service_metric_values(&self, service_name: &str, metric_name: &str, from: i64, to: i64) -> Result<Vec<Point>, String> {
+        let (from, to) = (from.to_string(), to.to_string());
+        points_from_json(field(&self.get(
+            &format!("/api/v0/services/{}/metrics", path_segment(service_name)),
+            &[("name", metric_name), ("from", &from), ("to", &to)],
+        )?, "metrics")?)
+    }
+}
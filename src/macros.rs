@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+
+use pest::inputs::Input;
+use pest::iterators::Pair;
+use pest::{self, Parser};
+
+use functions;
+use suggest::{did_you_mean, suggest};
+use {parse_metric, Metric, Span};
+
+#[derive(Parser)]
+#[grammar = "macros.pest"]
+pub struct MacroParser;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MacroError {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Node {
+    Call(String, Vec<Node>, Span),
+    Word(String, Span),
+    Quoted(String, Span),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+struct Definition {
+    params: Vec<String>,
+    body: Node,
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Library {
+    definitions: BTreeMap<String, Definition>,
+}
+
+fn error(span: Span, message: String) -> MacroError {
+    MacroError { span, message }
+}
+
+fn span_of<I: Input>(pair: &Pair<Rule, I>) -> Span {
+    let span = pair.clone().into_span();
+    Span { start: span.start(), end: span.end() }
+}
+
+fn parse_error<I: Input>(err: pest::Error<Rule, I>) -> MacroError {
+    let pos = match err {
+        pest::Error::ParsingError { ref pos, .. } | pest::Error::CustomErrorPos { ref pos, .. } => pos.pos(),
+        pest::Error::CustomErrorSpan { ref span, .. } => span.start(),
+    };
+    error(Span { start: pos, end: pos }, format!("{}", err))
+}
+
+fn convert_node<I: Input>(pair: Pair<Rule, I>) -> Node {
+    let span = span_of(&pair);
+    match pair.as_rule() {
+        Rule::expr => match pair.into_inner().next() {
+            Some(pair) => convert_node(pair),
+            None => Node::Word(String::new(), span),
+        },
+        Rule::call => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().map(|p| p.as_str().to_string()).unwrap_or_default();
+            Node::Call(name, inner.map(convert_node).collect(), span)
+        }
+        Rule::quoted => Node::Quoted(pair.as_str().to_string(), span),
+        _ => Node::Word(pair.as_str().to_string(), span),
+    }
+}
+
+fn print_node(node: &Node) -> String {
+    match *node {
+        Node::Call(ref name, ref args, _) => {
+            format!("{}({})", name, args.iter().map(print_node).collect::<Vec<_>>().join(", "))
+        }
+        Node::Word(ref text, _) | Node::Quoted(ref text, _) => text.clone(),
+    }
+}
+
+fn parameters(text: &str) -> Vec<(usize, usize, &str)> {
+    let mut found = Vec::new();
+    let mut offset = 0;
+    while let Some(i) = text[offset..].find('$').map(|i| offset + i) {
+        let braced = text[i + 1..].starts_with('{');
+        let start = if braced { i + 2 } else { i + 1 };
+        let len = text[start..].find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(text.len() - start);
+        let end = start + len;
+        if len == 0 || (braced && !text[end..].starts_with('}')) {
+            offset = i + 1;
+            continue;
+        }
+        let stop = if braced { end + 1 } else { end };
+        found.push((i, stop, &text[start..end]));
+        offset = stop;
+    }
+    found
+}
+
+fn splice(text: &str, bindings: &BTreeMap<&str, &Node>, span: Span) -> Result<String, MacroError> {
+    let mut output = String::new();
+    let mut last = 0;
+    for (start, end, name) in parameters(text) {
+        output.push_str(&text[last..start]);
+        match bindings.get(name) {
+            Some(Node::Word(value, _)) => output.push_str(value),
+            Some(Node::Quoted(value, _)) => output.push_str(&value[1..value.len() - 1]),
+            Some(Node::Call(..)) => {
+                return Err(error(span, format!("cannot splice an expression into {}", text)));
+            }
+            None => output.push_str(&text[start..end]),
+        }
+        last = end;
+    }
+    output.push_str(&text[last..]);
+    Ok(output)
+}
+
+fn substitute(node: &Node, bindings: &BTreeMap<&str, &Node>) -> Result<Node, MacroError> {
+    match *node {
+        Node::Call(ref name, ref args, span) => Ok(Node::Call(
+            name.clone(),
+            args.iter().map(|arg| substitute(arg, bindings)).collect::<Result<_, _>>()?,
+            span,
+        )),
+        Node::Word(ref text, span) => match parameters(text).as_slice() {
+            [(0, end, name)] if *end == text.len() && bindings.contains_key(name) => Ok(bindings[name].clone()),
+            _ => Ok(Node::Word(splice(text, bindings, span)?, span)),
+        },
+        Node::Quoted(ref text, span) => Ok(Node::Quoted(splice(text, bindings, span)?, span)),
+    }
+}
+
+impl Library {
+    pub fn parse(src: &str) -> Result<Library, MacroError> {
+        let pairs = MacroParser::parse_str(Rule::library, src).map_err(parse_error)?;
+        let mut library = Library::default();
+        for pair in pairs.flat_map(|pair| pair.into_inner()).filter(|pair| pair.as_rule() == Rule::definition) {
+            let span = span_of(&pair);
+            let mut inner = pair.into_inner();
+            let (name, params, body) = match (inner.next(), inner.next(), inner.next()) {
+                (Some(name), Some(params), Some(body)) => (name, params, body),
+                _ => return Err(error(span, "invalid macro definition".to_string())),
+            };
+            let name = name.as_str().to_string();
+            if functions::function(&name).is_some() {
+                return Err(error(span, format!("macro shadows the function {}", name)));
+            }
+            if library.definitions.contains_key(&name) {
+                return Err(error(span, format!("macro {} is defined twice", name)));
+            }
+            let params = params.into_inner().map(|p| p.as_str().to_string()).collect::<Vec<_>>();
+            if let Some(param) = params.iter().enumerate().find(|&(i, p)| params[..i].contains(p)).map(|(_, p)| p) {
+                return Err(error(span, format!("duplicate parameter {} of {}", param, name)));
+            }
+            let body = convert_node(body);
+            library.definitions.insert(name, Definition { params, body });
+        }
+        for (name, definition) in &library.definitions {
+            library.check_parameters(name, definition, &definition.body)?;
+            library.check_calls(&definition.body, &mut vec![name.clone()])?;
+        }
+        Ok(library)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.definitions.keys().cloned().collect()
+    }
+
+    fn check_parameters(&self, name: &str, definition: &Definition, node: &Node) -> Result<(), MacroError> {
+        match *node {
+            Node::Call(_, ref args, _) => {
+                for arg in args {
+                    self.check_parameters(name, definition, arg)?;
+                }
+            }
+            Node::Word(ref text, span) | Node::Quoted(ref text, span) => {
+                for (_, _, param) in parameters(text) {
+                    if !definition.params.iter().any(|p| p == param) {
+                        return Err(error(span, format!("unknown parameter ${} in {}", param, name)));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_calls(&self, node: &Node, stack: &mut Vec<String>) -> Result<(), MacroError> {
+        if let Node::Call(ref name, ref args, span) = *node {
+            for arg in args {
+                self.check_calls(arg, stack)?;
+            }
+            if let Some(definition) = self.definitions.get(name) {
+                self.check_arity(name, definition, args.len(), span)?;
+                if stack.contains(name) {
+                    return Err(error(span, format!("recursive macro: {} -> {}", stack.join(" -> "), name)));
+                }
+                stack.push(name.clone());
+                self.check_calls(&definition.body, stack)?;
+                stack.pop();
+            }
+        }
+        Ok(())
+    }
+
+    fn check_arity(&self, name: &str, definition: &Definition, count: usize, span: Span) -> Result<(), MacroError> {
+        if definition.params.len() == count {
+            Ok(())
+        } else {
+            Err(error(
+                span,
+                format!("{} expects {} arguments but got {}", name, definition.params.len(), count),
+            ))
+        }
+    }
+
+    fn expand_node(&self, node: &Node, warnings: &mut Vec<(Span, String)>) -> Result<Node, MacroError> {
+        match *node {
+            Node::Call(ref name, ref args, span) => {
+                let mut arg_warnings = Vec::new();
+                let args = args.iter().map(|arg| self.expand_node(arg, &mut arg_warnings)).collect::<Result<Vec<_>, _>>()?;
+                match self.definitions.get(name) {
+                    Some(definition) => {
+                        self.check_arity(name, definition, args.len(), span)?;
+                        let bindings = definition.params.iter().map(|p| p.as_str()).zip(args.iter()).collect();
+                        let body = substitute(&definition.body, &bindings).map_err(|e| error(span, e.message))?;
+                        let mut body_warnings = Vec::new();
+                        let expanded = self.expand_node(&body, &mut body_warnings).map_err(|e| error(span, e.message))?;
+                        // The arguments were expanded above and keep their spans, so their warnings come back
+                        // unchanged; the rest come from the body and are reported at the macro call.
+                        let body_warnings = body_warnings.into_iter().filter(|warning| !arg_warnings.contains(warning));
+                        let body_warnings = body_warnings.map(|(_, message)| (span, message)).collect::<Vec<_>>();
+                        warnings.extend(arg_warnings);
+                        warnings.extend(body_warnings);
+                        Ok(expanded)
+                    }
+                    None => {
+                        warnings.extend(arg_warnings);
+                        if functions::function(name).is_none() {
+                            let mut candidates = self.names();
+                            candidates.extend(functions::names().into_iter().map(|name| name.to_string()));
+                            let suggestions = did_you_mean(suggest(name, &candidates));
+                            warnings.push((span, format!("unknown function or macro: {}{}", name, suggestions)));
+                        }
+                        Ok(Node::Call(name.clone(), args, span))
+                    }
+                }
+            }
+            _ => Ok(node.clone()),
+        }
+    }
+
+    fn expand_source(&self, src: &str, warnings: &mut Vec<(Span, String)>) -> Result<Node, MacroError> {
+        let mut pairs = MacroParser::parse_str(Rule::whole_expr, src).map_err(parse_error)?;
+        let node = match pairs.next().and_then(|pair| pair.into_inner().next()) {
+            Some(pair) => convert_node(pair),
+            None => return Err(error(Span { start: 0, end: src.len() }, "empty expression".to_string())),
+        };
+        self.expand_node(&node, warnings)
+    }
+
+    pub fn expand_str(&self, src: &str) -> Result<String, MacroError> {
+        Ok(print_node(&self.expand_source(src, &mut Vec::new())?))
+    }
+
+    pub fn unknown_functions(&self, src: &str) -> Result<Vec<(Span, String)>, MacroError> {
+        let mut warnings = Vec::new();
+        self.expand_source(src, &mut warnings)?;
+        Ok(warnings)
+    }
+
+    pub fn expand(&self, src: &str) -> Result<Metric, MacroError> {
+        let expanded = self.expand_str(src)?;
+        parse_metric(&expanded).map_err(|e| error(Span { start: 0, end: src.len() }, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compact_print;
+
+    const LIBRARY: &str = "
+# error rate of the role
+errorRate(svc, role) = divide(
+  sum(role($svc:$role, custom.errors)),
+  sum(role($svc:${role}, custom.requests))
+)
+
+percent(metric) = scale($metric, 100)
+
+labeled(metric, name) = alias($metric, '$name (%)')
+";
+
+    #[test]
+    fn test_expand() {
+        let library = Library::parse(LIBRARY).unwrap();
+        assert_eq!(library.names(), vec!["errorRate", "labeled", "percent"]);
+        assert_eq!(
+            compact_print(library.expand("labeled(percent(errorRate(Blog, app)), Blog error rate)").unwrap()),
+            "alias(scale(divide(sum(role(Blog:app, custom.errors)), sum(role(Blog:app, custom.requests))), 100), 'Blog error rate (%)')"
+        );
+        assert_eq!(
+            library.expand("group(percent(host(22CXRB3pZmu, loadavg5)), errorRate(Blog))"),
+            Err(MacroError {
+                span: Span { start: 44, end: 59 },
+                message: "errorRate expects 2 arguments but got 1".to_string(),
+            })
+        );
+        assert_eq!(
+            library.expand("errorRate(host(22CXRB3pZmu, loadavg5), app)"),
+            Err(MacroError {
+                span: Span { start: 0, end: 43 },
+                message: "cannot splice an expression into $svc:$role".to_string(),
+            })
+        );
+        assert_eq!(
+            compact_print(library.expand("alias(errRate(Blog, app), x)").unwrap()),
+            "alias(errRate(Blog, app), x)"
+        );
+        assert_eq!(
+            library.unknown_functions("alias(errRate(Blog, app), percent(newFunction(host(a, b))))"),
+            Ok(vec![
+                (Span { start: 6, end: 24 }, "unknown function or macro: errRate (did you mean errorRate?)".to_string()),
+                (Span { start: 34, end: 57 }, "unknown function or macro: newFunction".to_string()),
+            ])
+        );
+        let library = Library::parse("next(x) = newFunction($x)\n").unwrap();
+        assert_eq!(
+            library.unknown_functions("avg(next(host(a, b)))"),
+            Ok(vec![(Span { start: 4, end: 20 }, "unknown function or macro: newFunction".to_string())])
+        );
+    }
+
+    #[test]
+    fn test_library_errors() {
+        assert_eq!(
+            Library::parse("a(x) = b($x)\nb(x) = avg(a($x))\n"),
+            Err(MacroError {
+                span: Span { start: 24, end: 29 },
+                message: "recursive macro: a -> b -> a".to_string(),
+            })
+        );
+        assert_eq!(
+            Library::parse("a(x) = b($x, 1)\nb(x) = avg($x)\n"),
+            Err(MacroError { span: Span { start: 7, end: 15 }, message: "b expects 1 arguments but got 2".to_string() })
+        );
+        assert_eq!(
+            Library::parse("a(x) = avg($y)\n"),
+            Err(MacroError { span: Span { start: 11, end: 13 }, message: "unknown parameter $y in a".to_string() })
+        );
+        assert!(Library::parse("avg(x) = max($x)\n").is_err());
+        assert!(Library::parse("a(x) = avg($x\n").is_err());
+    }
+}
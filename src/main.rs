@@ -1,15 +1,664 @@
+use std::collections::HashMap;
+use std::fs;
 use std::io::{self, Read};
 
+extern crate chrono_tz;
 extern crate mmpp;
 
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.first().map(|s| s.as_ref()) {
+        Some("validate") => validate(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("refs") => refs(&args[1..]),
+        Some("rename") => rename(&args[1..]),
+        Some("render") => render(&args[1..]),
+        Some("expand") => expand(&args[1..]),
+        Some("json") => json(&args[1..]),
+        Some("hcl") => hcl(&args[1..]),
+        Some("markdown") => markdown(&args[1..]),
+        Some("to-graphite") => to_graphite(&args[1..]),
+        Some("from-graphite") => from_graphite(&args[1..]),
+        Some("to-promql") => to_promql(&args[1..]),
+        Some("tree") => tree(&args[1..]),
+        Some("explain") => explain(&args[1..]),
+        Some("plot") => plot(&args[1..]),
+        Some("svg") => svg(&args[1..]),
+        Some("functions") => functions(&args[1..]),
+        Some("complete") => complete(&args[1..]),
+        _ => format(&args),
+    };
+    if let Err(err) = result {
+        println!("{}", err);
+        std::process::exit(1)
+    }
+}
+
+struct Args {
+    options: HashMap<String, Vec<String>>,
+    positionals: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String], options: &[&str], switches: &[&str]) -> Result<Args, String> {
+        let mut parsed = Args { options: HashMap::new(), positionals: Vec::new() };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with("--") {
+                parsed.positionals.push(arg.clone());
+                continue;
+            }
+            let (name, value) = match arg.find('=') {
+                Some(i) => (&arg[2..i], Some(arg[i + 1..].to_string())),
+                None => (&arg[2..], None),
+            };
+            if switches.contains(&name) && value.is_none() {
+                parsed.options.entry(name.to_string()).or_default().push(String::new());
+            } else if options.contains(&name) {
+                let value = match value {
+                    Some(value) => value,
+                    None => iter.next().ok_or_else(|| format!("missing value for --{}", name))?.clone(),
+                };
+                parsed.options.entry(name.to_string()).or_default().push(value);
+            } else {
+                return Err(format!("unknown option: {}", arg));
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(|values| values.last()).map(|s| s.as_ref())
+    }
+
+    fn values(&self, name: &str) -> &[String] {
+        self.options.get(name).map(|values| values.as_slice()).unwrap_or(&[])
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+}
+
+fn read_stdin() -> String {
     let mut buffer = String::new();
     let _ = io::stdin().read_to_string(&mut buffer);
-    match mmpp::parse_metric(buffer.as_ref()) {
-        Ok(metric) => println!("{}", mmpp::pretty_print(metric)),
-        Err(err) => {
-            println!("{}", err);
+    buffer
+}
+
+fn read_file(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn is_yaml(path: &str) -> bool {
+    path.ends_with(".yaml") || path.ends_with(".yml")
+}
+
+fn read_inputs(args: &Args) -> Result<Vec<(Option<&String>, String)>, String> {
+    if args.positionals.is_empty() {
+        Ok(vec![(None, read_stdin())])
+    } else {
+        args.positionals.iter().map(|path| Ok((Some(path), read_file(path)?))).collect()
+    }
+}
+
+fn write_formatted(path: &str, src: &str, formatted: String) -> Result<(), String> {
+    if formatted != src {
+        fs::write(path, formatted).map_err(|e| format!("{}: {}", path, e))?;
+        println!("{}", path);
+    }
+    Ok(())
+}
+
+fn unknown_functions_policy(args: &Args) -> Result<&str, String> {
+    match args.value("unknown-functions").unwrap_or("warn") {
+        policy @ "allow" | policy @ "warn" | policy @ "error" => Ok(policy),
+        policy => Err(format!("unknown --unknown-functions: {} (expected allow, warn or error)", policy)),
+    }
+}
+
+fn located(src: &str, warnings: Vec<(mmpp::Span, String)>) -> Vec<(usize, usize, String)> {
+    warnings
+        .into_iter()
+        .map(|(span, message)| {
+            let (line, col) = span.line_col(src);
+            (line, col, message)
+        })
+        .collect()
+}
+
+fn source_warnings(src: &str) -> Vec<(usize, usize, String)> {
+    let warnings = mmpp::recover::parse_recovering(src).warnings;
+    located(src, warnings.into_iter().map(|warning| (warning.span, warning.message)).collect())
+}
+
+fn report_unknown_functions(policy: &str, name: &str, warnings: Vec<(usize, usize, String)>) -> Result<(), String> {
+    match policy {
+        "warn" => {
+            for (line, col, message) in warnings {
+                eprintln!("{}{}:{}: warning: {}", name, line, col, message);
+            }
+            Ok(())
+        }
+        "error" if !warnings.is_empty() => Err(warnings
+            .into_iter()
+            .map(|(line, col, message)| format!("{}{}:{}: {}", name, line, col, message))
+            .collect::<Vec<_>>()
+            .join("\n")),
+        _ => Ok(()),
+    }
+}
+
+fn load_dialect(args: &Args) -> Result<mmpp::dialect::Dialect, String> {
+    match args.value("dialect") {
+        Some(path) if is_yaml(path) => mmpp::dialect::Dialect::from_yaml(&read_file(path)?),
+        Some(path) => mmpp::dialect::Dialect::from_json(&read_file(path)?),
+        None => Ok(mmpp::dialect::Dialect::default()),
+    }
+}
+
+fn format(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["unknown-functions", "dialect"], &[])?;
+    let policy = unknown_functions_policy(&args)?;
+    let dialect = load_dialect(&args)?;
+    let src = read_stdin();
+    let metric = mmpp::parse_metric_with_dialect(&src, &dialect)?;
+    report_unknown_functions(policy, "", source_warnings(&src))?;
+    println!("{}", mmpp::pretty_print(metric));
+    Ok(())
+}
+
+fn load_inventory(args: &Args) -> Result<mmpp::validate::Inventory, String> {
+    if let Some(path) = args.value("inventory") {
+        let src = read_file(path)?;
+        if is_yaml(path) {
+            mmpp::validate::Inventory::from_yaml(&src)
+        } else {
+            mmpp::validate::Inventory::from_json(&src)
+        }
+    } else if args.has("mackerel") {
+        load_mackerel_inventory()
+    } else {
+        Err("validate requires --inventory FILE or --mackerel".to_string())
+    }
+}
+
+#[cfg(feature = "mackerel")]
+fn load_mackerel_inventory() -> Result<mmpp::validate::Inventory, String> {
+    mmpp::validate::Inventory::from_source(&mmpp::mackerel::Client::from_env()?)
+}
+
+#[cfg(not(feature = "mackerel"))]
+fn load_mackerel_inventory() -> Result<mmpp::validate::Inventory, String> {
+    Err("--mackerel requires mmpp to be built with the mackerel feature".to_string())
+}
+
+fn validate(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["inventory", "dialect", "unknown-functions"], &["mackerel"])?;
+    let policy = unknown_functions_policy(&args)?;
+    let inventory = load_inventory(&args)?;
+    let dialect = load_dialect(&args)?;
+    let src = read_stdin();
+    let problems = mmpp::validate::validate_with_dialect(&src, &inventory, &dialect)?;
+    report_unknown_functions(policy, "", source_warnings(&src))?;
+    for problem in &problems {
+        let (line, col) = problem.span.line_col(&src);
+        if problem.suggestions.is_empty() {
+            println!("{}:{}: {}", line, col, problem.message);
+        } else {
+            println!(
+                "{}:{}: {} (did you mean {}?)",
+                line,
+                col,
+                problem.message,
+                problem.suggestions.join(", ")
+            );
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        std::process::exit(1)
+    }
+}
+
+fn refs(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["unknown-functions"], &["json"])?;
+    let policy = unknown_functions_policy(&args)?;
+    let mut refs = mmpp::references::References::default();
+    if args.positionals.is_empty() {
+        let src = read_stdin();
+        refs.merge(mmpp::references::references(&mmpp::parse_metric(&src)?));
+        report_unknown_functions(policy, "", source_warnings(&src))?;
+    }
+    for path in &args.positionals {
+        let src = read_file(path)?;
+        let metric = mmpp::parse_metric(&src).map_err(|e| format!("{}: {}", path, e))?;
+        report_unknown_functions(policy, &format!("{}:", path), source_warnings(&src))?;
+        refs.merge(mmpp::references::references(&metric));
+    }
+    if args.has("json") {
+        println!("{}", refs.to_json());
+    } else {
+        let join = |values: Vec<String>| values.join(", ");
+        println!("hosts: {}", join(refs.hosts.iter().cloned().collect()));
+        println!("services: {}", join(refs.services.iter().cloned().collect()));
+        println!("roles: {}", join(refs.role_names()));
+        println!("metrics: {}", join(refs.metric_names.iter().cloned().collect()));
+        println!("functions: {}", join(refs.functions.iter().cloned().collect()));
+    }
+    Ok(())
+}
+
+fn rename(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["host", "service", "role", "metric", "unknown-functions"], &["aliases"])?;
+    let policy = unknown_functions_policy(&args)?;
+    let mut renames = mmpp::rename::Renames { aliases: args.has("aliases"), ..Default::default() };
+    for spec in args.values("host") {
+        renames.add_host(spec)?;
+    }
+    for spec in args.values("service") {
+        renames.add_service(spec)?;
+    }
+    for spec in args.values("role") {
+        renames.add_role(spec)?;
+    }
+    for spec in args.values("metric") {
+        renames.add_metric(spec)?;
+    }
+    if args.positionals.is_empty() {
+        let src = read_stdin();
+        let metric = mmpp::parse_metric(&src)?;
+        report_unknown_functions(policy, "", source_warnings(&src))?;
+        println!("{}", mmpp::pretty_print(mmpp::rename::rename(metric, &renames)));
+    }
+    for path in &args.positionals {
+        let src = read_file(path)?;
+        let metric = mmpp::parse_metric(&src).map_err(|e| format!("{}: {}", path, e))?;
+        report_unknown_functions(policy, &format!("{}:", path), source_warnings(&src))?;
+        let renamed = mmpp::rename::rename(metric.clone(), &renames);
+        if renamed != metric {
+            fs::write(path, format!("{}\n", mmpp::pretty_print(renamed))).map_err(|e| format!("{}: {}", path, e))?;
+            println!("{}", path);
+        }
+    }
+    Ok(())
+}
+
+fn render(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["var", "vars-file", "unknown-functions"], &[])?;
+    let policy = unknown_functions_policy(&args)?;
+    let mut bindings = match args.value("vars-file") {
+        Some(path) => mmpp::template::bindings_from_csv(&read_file(path)?).map_err(|e| format!("{}: {}", path, e))?,
+        None => mmpp::template::Bindings::new(),
+    };
+    for var in args.values("var") {
+        let i = var.find('=').ok_or_else(|| format!("expected NAME=VALUE: {}", var))?;
+        bindings.entry(var[..i].to_string()).or_default().push(var[i + 1..].to_string());
+    }
+    let (name, template) = match args.positionals.first() {
+        Some(path) => (format!("{}:", path), read_file(path)?),
+        None => (String::new(), read_stdin()),
+    };
+    let metric = mmpp::template::render(&template, &bindings)?;
+    let warnings = mmpp::template::unknown_functions(&template, &bindings)?;
+    report_unknown_functions(policy, &name, located(&template, warnings))?;
+    println!("{}", mmpp::pretty_print(metric));
+    Ok(())
+}
+
+fn expand(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["library", "unknown-functions"], &[])?;
+    let policy = unknown_functions_policy(&args)?;
+    let path = args.value("library").ok_or_else(|| "--library FILE is required".to_string())?;
+    let library_src = read_file(path)?;
+    let library = mmpp::macros::Library::parse(&library_src).map_err(|e| {
+        let (line, col) = e.span.line_col(&library_src);
+        format!("{}:{}:{}: {}", path, line, col, e.message)
+    })?;
+    let (name, src) = match args.positionals.first() {
+        Some(path) => (format!("{}:", path), read_file(path)?),
+        None => (String::new(), read_stdin()),
+    };
+    let located_error = |e: mmpp::macros::MacroError| {
+        let (line, col) = e.span.line_col(&src);
+        format!("{}{}:{}: {}", name, line, col, e.message)
+    };
+    let metric = library.expand(&src).map_err(&located_error)?;
+    let warnings = library.unknown_functions(&src).map_err(&located_error)?;
+    report_unknown_functions(policy, &name, located(&src, warnings))?;
+    println!("{}", mmpp::pretty_print(metric));
+    Ok(())
+}
+
+fn check(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["unknown-functions", "dialect"], &[])?;
+    let policy = unknown_functions_policy(&args)?;
+    let dialect = load_dialect(&args)?;
+    let mut failed = false;
+    for (path, src) in read_inputs(&args)? {
+        let name = path.map(|path| format!("{}:", path)).unwrap_or_default();
+        let recovery = mmpp::recover::parse_recovering(&src);
+        let mut problems = recovery.errors.into_iter().map(|err| (err, "")).collect::<Vec<_>>();
+        if problems.is_empty() {
+            problems.extend(mmpp::dialect::check(&src, &dialect)?.into_iter().map(|err| (err, "")));
+        }
+        match policy {
+            "warn" => problems.extend(recovery.warnings.into_iter().map(|warning| (warning, "warning: "))),
+            "error" => problems.extend(recovery.warnings.into_iter().map(|warning| (warning, ""))),
+            _ => {}
+        }
+        problems.sort_by_key(|(err, _)| err.span.start);
+        for (err, level) in problems {
+            let (line, col) = err.span.line_col(&src);
+            println!("{}{}:{}: {}{}", name, line, col, level, err.message);
+            failed |= level.is_empty();
+        }
+    }
+    if failed {
+        std::process::exit(1)
+    }
+    Ok(())
+}
+
+fn json(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["key", "unknown-functions"], &["check"])?;
+    let policy = unknown_functions_policy(&args)?;
+    let keys = if args.has("key") {
+        args.values("key").iter().map(|key| key.as_str()).collect::<Vec<_>>()
+    } else {
+        mmpp::json::DEFAULT_KEYS.to_vec()
+    };
+    let inputs = read_inputs(&args)?;
+    for (path, src) in &inputs {
+        let name = path.map(|path| format!("{}:", path)).unwrap_or_default();
+        let warnings = mmpp::json::unknown_functions(src, &keys)
+            .map_err(|e| format!("{}{}", name, e))?
+            .into_iter()
+            .map(|(expression, span, message)| {
+                let (line, col) = span.line_col(src);
+                (line, col, format!("{}: {}", expression.path, message))
+            })
+            .collect();
+        report_unknown_functions(policy, &name, warnings)?;
+    }
+    if args.has("check") {
+        let mut failed = false;
+        for (path, src) in inputs {
+            let name = path.map(|path| format!("{}:", path)).unwrap_or_default();
+            for (expression, span, err) in mmpp::json::check_expressions(&src, &keys).map_err(|e| format!("{}{}", name, e))? {
+                let (line, col) = span.line_col(&src);
+                println!("{}{}:{}: {}: {}", name, line, col, expression.path, err);
+                failed = true;
+            }
+        }
+        if failed {
+            std::process::exit(1)
+        }
+        return Ok(());
+    }
+    for (path, src) in inputs {
+        match path {
+            Some(path) => {
+                let formatted = mmpp::json::format_expressions(&src, &keys)
+                    .map_err(|e| e.lines().map(|line| format!("{}:{}", path, line)).collect::<Vec<_>>().join("\n"))?;
+                write_formatted(path, &src, formatted)?;
+            }
+            None => print!("{}", mmpp::json::format_expressions(&src, &keys)?),
+        }
+    }
+    Ok(())
+}
+
+fn hcl(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["key", "unknown-functions"], &["check"])?;
+    let policy = unknown_functions_policy(&args)?;
+    let keys = if args.has("key") {
+        args.values("key").iter().map(|key| key.as_str()).collect::<Vec<_>>()
+    } else {
+        mmpp::hcl::DEFAULT_KEYS.to_vec()
+    };
+    let inputs = read_inputs(&args)?;
+    for (path, src) in &inputs {
+        let name = path.map(|path| format!("{}:", path)).unwrap_or_default();
+        let warnings = mmpp::hcl::unknown_functions(src, &keys).map_err(|e| format!("{}{}", name, e))?;
+        report_unknown_functions(policy, &name, located(src, warnings))?;
+    }
+    if args.has("check") {
+        let mut failed = false;
+        for (path, src) in inputs {
+            let name = path.map(|path| format!("{}:", path)).unwrap_or_default();
+            for (span, err) in mmpp::hcl::check_attributes(&src, &keys).map_err(|e| format!("{}{}", name, e))? {
+                let (line, col) = span.line_col(&src);
+                println!("{}{}:{}: {}", name, line, col, err);
+                failed = true;
+            }
+        }
+        if failed {
+            std::process::exit(1)
+        }
+        return Ok(());
+    }
+    for (path, src) in inputs {
+        match path {
+            Some(path) => {
+                let formatted = mmpp::hcl::format_heredocs(&src, &keys).map_err(|e| format!("{}:{}", path, e))?;
+                write_formatted(path, &src, formatted)?;
+            }
+            None => print!("{}", mmpp::hcl::format_heredocs(&src, &keys)?),
+        }
+    }
+    Ok(())
+}
+
+fn markdown(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["lang", "unknown-functions"], &["check"])?;
+    let policy = unknown_functions_policy(&args)?;
+    let language = args.value("lang").unwrap_or(mmpp::markdown::DEFAULT_LANGUAGE);
+    let inputs = read_inputs(&args)?;
+    for (path, src) in &inputs {
+        let name = path.map(|path| format!("{}:", path)).unwrap_or_default();
+        report_unknown_functions(policy, &name, mmpp::markdown::unknown_functions(src, language))?;
+    }
+    if args.has("check") {
+        let mut failed = false;
+        for (path, src) in inputs {
+            let name = path.map(|path| format!("{}:", path)).unwrap_or_default();
+            for (line, col, err) in mmpp::markdown::check_blocks(&src, language) {
+                println!("{}{}:{}: {}", name, line, col, err);
+                failed = true;
+            }
+        }
+        if failed {
             std::process::exit(1)
         }
+        return Ok(());
+    }
+    for (path, src) in inputs {
+        match path {
+            Some(path) => {
+                let formatted =
+                    mmpp::markdown::format_blocks(&src, language).map_err(|e| format!("{}:{}", path, e))?;
+                write_formatted(path, &src, formatted)?;
+            }
+            None => print!("{}", mmpp::markdown::format_blocks(&src, language)?),
+        }
+    }
+    Ok(())
+}
+
+fn load_path_mapping(args: &Args) -> Result<mmpp::graphite::PathMapping, String> {
+    match args.value("mapping") {
+        Some(path) if is_yaml(path) => mmpp::graphite::PathMapping::from_yaml(&read_file(path)?),
+        Some(path) => mmpp::graphite::PathMapping::from_json(&read_file(path)?),
+        None => Ok(mmpp::graphite::PathMapping::default()),
+    }
+}
+
+fn read_metric(args: &Args) -> Result<mmpp::Metric, String> {
+    let policy = unknown_functions_policy(args)?;
+    let src = read_stdin();
+    let metric = mmpp::parse_metric(&src)?;
+    report_unknown_functions(policy, "", source_warnings(&src))?;
+    Ok(metric)
+}
+
+fn to_graphite(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["mapping", "unknown-functions"], &[])?;
+    let mapping = load_path_mapping(&args)?;
+    let metric = read_metric(&args)?;
+    println!("{}", mmpp::graphite::to_graphite(&metric, &mapping)?);
+    Ok(())
+}
+
+fn from_graphite(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["mapping"], &[])?;
+    let mapping = load_path_mapping(&args)?;
+    let src = read_stdin();
+    let mut failed = false;
+    for (i, target) in src.lines().enumerate().filter(|(_, target)| !target.trim().is_empty()) {
+        match mmpp::graphite::from_graphite(target.trim(), &mapping) {
+            Ok(metric) => println!("{}", mmpp::pretty_print(metric)),
+            Err(err) => {
+                let offset = target.len() - target.trim_start().len();
+                println!("{}:{}: {}", i + 1, offset + err.span.start + 1, err.summary());
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1)
+    }
+    Ok(())
+}
+
+fn to_promql(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["labels", "unknown-functions"], &[])?;
+    let mapping = match args.value("labels") {
+        Some(path) if is_yaml(path) => mmpp::promql::LabelMapping::from_yaml(&read_file(path)?)?,
+        Some(path) => mmpp::promql::LabelMapping::from_json(&read_file(path)?)?,
+        None => mmpp::promql::LabelMapping::default(),
+    };
+    let metric = read_metric(&args)?;
+    let conversion = mmpp::promql::to_promql(&metric, &mapping);
+    for warning in conversion.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    println!("{}", conversion.query);
+    Ok(())
+}
+
+fn tree(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["format", "unknown-functions"], &[])?;
+    let metric = read_metric(&args)?;
+    let output = match args.value("format") {
+        Some("ascii") | None => mmpp::tree::to_ascii(&metric),
+        Some("dot") => mmpp::tree::to_dot(&metric),
+        Some("mermaid") => mmpp::tree::to_mermaid(&metric),
+        Some(format) => return Err(format!("invalid --format: {}", format)),
+    };
+    print!("{}", output);
+    Ok(())
+}
+
+fn explain(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["unknown-functions"], &[])?;
+    let metric = read_metric(&args)?;
+    println!("{}", mmpp::explain::explain(&metric));
+    Ok(())
+}
+
+fn functions(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &[], &[])?;
+    match args.positionals.first() {
+        Some(name) => match mmpp::functions::function(name) {
+            Some(function) => print!("{}", function.help()),
+            None => {
+                let suggestions = mmpp::suggest::suggest_function(name);
+                return Err(format!("unknown function: {}{}", name, mmpp::suggest::did_you_mean(suggestions)));
+            }
+        },
+        None => {
+            let signatures = mmpp::functions::all().map(|function| function.signature()).collect::<Vec<_>>();
+            let width = signatures.iter().map(|signature| signature.len()).max().unwrap_or(0);
+            for (signature, function) in signatures.iter().zip(mmpp::functions::all()) {
+                println!("{:<width$}  {}", signature, function.description, width = width);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn complete(args: &[String]) -> Result<(), String> {
+    Args::parse(args, &[], &[])?;
+    let src = read_stdin();
+    let (_, names) = mmpp::functions::complete(src.trim_end_matches('\n'));
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+fn parse_number<T: std::str::FromStr>(args: &Args, name: &str, default: T) -> Result<T, String> {
+    match args.value(name) {
+        Some(value) => value.parse().map_err(|_| format!("invalid --{}: {}", name, value)),
+        None => Ok(default),
+    }
+}
+
+fn parse_timezone(args: &Args) -> Result<chrono_tz::Tz, String> {
+    args.value("timezone").unwrap_or("UTC").parse().map_err(|e| format!("invalid --timezone: {}", e))
+}
+
+fn evaluate_data(args: &Args) -> Result<(Vec<mmpp::eval::Evaluated>, i64, i64), String> {
+    let path = args.value("data").ok_or_else(|| "--data FILE is required".to_string())?;
+    let src = read_file(path)?;
+    let source = if is_yaml(path) {
+        mmpp::source::LocalSource::from_yaml(&src)?
+    } else {
+        mmpp::source::LocalSource::from_json(&src)?
+    };
+    let (data_from, data_to) = source.time_range().unwrap_or((0, 0));
+    let to = parse_number(args, "to", data_to)?;
+    let from = match args.value("range") {
+        Some(range) => to - range.parse::<mmpp::Duration>()?.seconds(),
+        None => parse_number(args, "from", data_from)?,
+    };
+    let metric = read_metric(args)?;
+    Ok((mmpp::eval::evaluate(&source, &metric, from, to)?, from, to))
+}
+
+fn plot(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["data", "from", "to", "range", "width", "height", "timezone", "unknown-functions"], &["ascii"])?;
+    let (evaluated, from, to) = evaluate_data(&args)?;
+    let options = mmpp::plot::PlotOptions {
+        width: parse_number(&args, "width", 80)?,
+        height: parse_number(&args, "height", 20)?,
+        from,
+        to,
+        ascii: args.has("ascii"),
+        timezone: parse_timezone(&args)?,
+    };
+    print!("{}", mmpp::plot::plot(&evaluated, &options)?);
+    Ok(())
+}
+
+fn svg(args: &[String]) -> Result<(), String> {
+    let args = Args::parse(args, &["data", "from", "to", "range", "width", "height", "timezone", "output", "unknown-functions"], &[])?;
+    let (evaluated, from, to) = evaluate_data(&args)?;
+    let options = mmpp::svg::SvgOptions {
+        width: parse_number(&args, "width", 800)?,
+        height: parse_number(&args, "height", 300)?,
+        from,
+        to,
+        timezone: parse_timezone(&args)?,
+    };
+    let svg = mmpp::svg::render_svg(&evaluated, &options)?;
+    match args.value("output") {
+        Some(path) => fs::write(path, svg).map_err(|e| format!("{}: {}", path, e)),
+        None => {
+            print!("{}", svg);
+            Ok(())
+        }
     }
 }
@@ -0,0 +1,203 @@
+use recover::parse_recovering;
+use {parse_metric_spanned, pretty_print, Span};
+
+pub const DEFAULT_LANGUAGE: &str = "mackerel";
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub span: Span,
+    pub line: usize,
+    pub value: String,
+    indents: Vec<usize>,
+}
+
+impl Block {
+    pub fn line_col(&self, pos: usize) -> (usize, usize) {
+        let (line, col) = Span { start: pos, end: pos }.line_col(&self.value);
+        (self.line + line - 1, col + self.indents.get(line - 1).cloned().unwrap_or(0))
+    }
+}
+
+pub fn find_blocks(src: &str, language: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    let mut lines = src
+        .split_inclusive('\n')
+        .map(|line| {
+            offset += line.len();
+            (offset - line.len(), line)
+        })
+        .enumerate();
+    while let Some((_, (_, line))) = lines.next() {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        let trimmed = line[indent..].trim_end();
+        let fence = match trimmed.chars().next() {
+            Some(c @ '`') | Some(c @ '~') if indent < 4 => c,
+            _ => continue,
+        };
+        let fence_len = trimmed.len() - trimmed.trim_start_matches(fence).len();
+        let info = &trimmed[fence_len..];
+        if fence_len < 3 || (fence == '`' && info.contains('`')) {
+            continue;
+        }
+        let is_target = info.split_whitespace().next() == Some(language);
+        let mut block = None;
+        let mut value = String::new();
+        let mut indents = Vec::new();
+        let mut end = src.len();
+        for (i, (start, line)) in lines.by_ref() {
+            let closing = line.trim();
+            if closing.len() >= fence_len
+                && closing.chars().all(|c| c == fence)
+                && line.len() - line.trim_start_matches(' ').len() < 4
+            {
+                end = start;
+                break;
+            }
+            if block.is_none() {
+                block = Some((i + 1, start));
+            }
+            let skip = indent.min(line.len() - line.trim_start_matches(' ').len());
+            value.push_str(&line[skip..]);
+            indents.push(skip);
+        }
+        if let (true, Some((line, start))) = (is_target, block) {
+            blocks.push(Block { span: Span { start, end }, line, value, indents });
+        }
+    }
+    blocks
+}
+
+pub fn check_blocks(src: &str, language: &str) -> Vec<(usize, usize, String)> {
+    find_blocks(src, language)
+        .into_iter()
+        .filter_map(|block| match parse_metric_spanned(&block.value) {
+            Ok(_) => None,
+            Err(err) => {
+                let (line, col) = block.line_col(err.span.start);
+                Some((line, col, err.summary().to_string()))
+            }
+        })
+        .collect()
+}
+
+pub fn unknown_functions(src: &str, language: &str) -> Vec<(usize, usize, String)> {
+    find_blocks(src, language)
+        .into_iter()
+        .flat_map(|block| {
+            let warnings = parse_recovering(&block.value).warnings;
+            warnings.into_iter().map(move |warning| {
+                let (line, col) = block.line_col(warning.span.start);
+                (line, col, warning.message)
+            })
+        })
+        .collect()
+}
+
+pub fn format_blocks(src: &str, language: &str) -> Result<String, String> {
+    let mut output = String::new();
+    let mut last = 0;
+    for block in find_blocks(src, language) {
+        let metric = parse_metric_spanned(&block.value).map_err(|err| {
+            let (line, col) = block.line_col(err.span.start);
+            format!("{}:{}: {}", line, col, err.summary())
+        })?;
+        let body = &src[block.span.start..block.span.end];
+        let indent = &body[..body.len() - body.trim_start_matches(' ').len()];
+        output.push_str(&src[last..block.span.start]);
+        for line in pretty_print(metric).lines() {
+            output.push_str(indent);
+            output.push_str(line);
+            output.push('\n');
+        }
+        last = block.span.end;
+    }
+    output.push_str(&src[last..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNBOOK: &str = "# Runbook
+
+Check the load of the database.
+
+```mackerel
+group(host(22CXRB3pZmu, loadavg5), avg(roleSlots(Blog:db, loadavg5)))
+```
+
+```sh
+mkr hosts
+```
+
+- Check the error rate.
+  ````mackerel title=errors
+  divide(service(Blog, errors),
+    service(Blog, requests))
+  ````
+
+~~~mackerel
+avg(host(22CXRB3pZmu))
+~~~
+";
+
+    #[test]
+    fn test_find_blocks() {
+        let blocks = find_blocks(RUNBOOK, DEFAULT_LANGUAGE);
+        assert_eq!(
+            blocks.iter().map(|b| (b.line, b.value.as_str())).collect::<Vec<_>>(),
+            vec![
+                (6, "group(host(22CXRB3pZmu, loadavg5), avg(roleSlots(Blog:db, loadavg5)))\n"),
+                (15, "divide(service(Blog, errors),\n  service(Blog, requests))\n"),
+                (20, "avg(host(22CXRB3pZmu))\n"),
+            ]
+        );
+        assert_eq!(find_blocks(RUNBOOK, "sh").len(), 1);
+    }
+
+    #[test]
+    fn test_check_blocks() {
+        assert_eq!(check_blocks(RUNBOOK, DEFAULT_LANGUAGE), vec![(20, 5, "host expects 2 arguments but got 1".to_string())]);
+        let src = RUNBOOK.replace("    service(Blog, requests))", "    service(Blog))");
+        assert_eq!(
+            check_blocks(&src, DEFAULT_LANGUAGE),
+            vec![
+                (16, 5, "service expects 2 arguments but got 1".to_string()),
+                (20, 5, "host expects 2 arguments but got 1".to_string()),
+            ]
+        );
+        assert_eq!(check_blocks("- x\n  ```mackerel\n  avg(host(abc))\n  ```\n", DEFAULT_LANGUAGE)[0].1, 7);
+    }
+
+    #[test]
+    fn test_unknown_functions() {
+        assert!(unknown_functions(RUNBOOK, DEFAULT_LANGUAGE).is_empty());
+        let src = RUNBOOK.replace("    service(Blog, requests))", "    services(Blog, requests))");
+        assert_eq!(
+            unknown_functions(&src, DEFAULT_LANGUAGE),
+            vec![(16, 5, "unknown function: services (did you mean service?)".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_format_blocks() {
+        let src = RUNBOOK.replace("avg(host(22CXRB3pZmu))", "avg(host(22CXRB3pZmu, loadavg5))");
+        let formatted = format_blocks(&src, DEFAULT_LANGUAGE).unwrap();
+        assert_eq!(
+            formatted,
+            src.replace(
+                "group(host(22CXRB3pZmu, loadavg5), avg(roleSlots(Blog:db, loadavg5)))\n",
+                "group(\n  host(22CXRB3pZmu, loadavg5),\n  avg(roleSlots(Blog:db, loadavg5))\n)\n"
+            ).replace(
+                "  divide(service(Blog, errors),\n    service(Blog, requests))\n",
+                "  divide(\n    service(Blog, errors),\n    service(Blog, requests)\n  )\n"
+            )
+        );
+        assert_eq!(format_blocks(&formatted, DEFAULT_LANGUAGE).unwrap(), formatted);
+        assert_eq!(format_blocks(RUNBOOK, DEFAULT_LANGUAGE), Err("20:5: host expects 2 arguments but got 1".to_string()));
+        let src = formatted.replace("    service(Blog, errors),", "    service(Blog),");
+        assert_eq!(format_blocks(&src, DEFAULT_LANGUAGE), Err("19:5: service expects 2 arguments but got 1".to_string()));
+    }
+}
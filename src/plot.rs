@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+
+use chrono::TimeZone;
+use chrono_tz::Tz;
+
+use eval::Evaluated;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PlotOptions {
+    pub width: usize,
+    pub height: usize,
+    pub from: i64,
+    pub to: i64,
+    pub ascii: bool,
+    pub timezone: Tz,
+}
+
+struct Glyphs {
+    markers: &'static [char],
+    areas: &'static [char],
+    vertical: char,
+    horizontal: char,
+    corner: char,
+}
+
+const UNICODE: Glyphs = Glyphs {
+    markers: &['●', '◆', '▲', '■', '○', '◇', '△', '□'],
+    areas: &['█', '▓', '▒', '░'],
+    vertical: '│',
+    horizontal: '─',
+    corner: '└',
+};
+
+const ASCII: Glyphs = Glyphs {
+    markers: &['*', '+', 'o', 'x', '@', '%', '&', '$'],
+    areas: &['#', '=', ':', '.'],
+    vertical: '|',
+    horizontal: '-',
+    corner: '+',
+};
+
+pub struct Band {
+    pub name: String,
+    pub stacked: bool,
+    pub lower: Vec<(i64, f64)>,
+    pub upper: Vec<(i64, f64)>,
+}
+
+pub fn stack_bands(evaluated: &[Evaluated]) -> Vec<Band> {
+    let mut base = BTreeMap::new();
+    evaluated
+        .iter()
+        .map(|e| {
+            let points = e.series.points.iter().map(|p| (p.time, p.value));
+            if e.stacked {
+                let lower = points.clone().map(|(t, _)| (t, *base.get(&t).unwrap_or(&0.0))).collect::<Vec<_>>();
+                let upper = points.map(|(t, v)| (t, v + *base.get(&t).unwrap_or(&0.0))).collect::<Vec<_>>();
+                for &(t, v) in &upper {
+                    base.insert(t, v);
+                }
+                Band { name: e.series.name.clone(), stacked: true, lower, upper }
+            } else {
+                Band { name: e.series.name.clone(), stacked: false, lower: Vec::new(), upper: points.collect() }
+            }
+        })
+        .collect()
+}
+
+pub fn value_range(bands: &[Band]) -> Option<(f64, f64)> {
+    let values = bands
+        .iter()
+        .flat_map(|b| b.upper.iter().chain(b.lower.iter()).map(|&(_, v)| v))
+        .filter(|v| v.is_finite())
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        return None;
+    }
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min).min(0.0);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max > min {
+        Some((min, max))
+    } else {
+        Some((min - 1.0, max + 1.0))
+    }
+}
+
+pub fn format_value(value: f64) -> String {
+    let abs = value.abs();
+    let (scaled, suffix) = if abs >= 1e12 {
+        (value / 1e12, "T")
+    } else if abs >= 1e9 {
+        (value / 1e9, "G")
+    } else if abs >= 1e6 {
+        (value / 1e6, "M")
+    } else if abs >= 1e3 {
+        (value / 1e3, "k")
+    } else {
+        (value, "")
+    };
+    let s = format!("{:.2}", scaled);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", if s == "-0" { "0" } else { s }, suffix)
+}
+
+pub fn format_time(time: i64, span: i64, timezone: &Tz) -> String {
+    let format = if span <= 2 * 86400 { "%H:%M" } else { "%m-%d" };
+    match timezone.timestamp_opt(time, 0).single() {
+        Some(datetime) => datetime.format(format).to_string(),
+        None => time.to_string(),
+    }
+}
+
+pub fn plot(evaluated: &[Evaluated], options: &PlotOptions) -> Result<String, String> {
+    if options.height < 2 {
+        return Err(format!("height must be at least 2 rows: {}", options.height));
+    }
+    let glyphs = if options.ascii { &ASCII } else { &UNICODE };
+    let bands = stack_bands(evaluated);
+    let (min, max) = match value_range(&bands) {
+        Some(range) => range,
+        None => return Ok("no data\n".to_string()),
+    };
+    let labels = [max, (max + min) / 2.0, min].iter().map(|&v| format_value(v)).collect::<Vec<_>>();
+    let label_width = labels.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    let width = options.width.saturating_sub(label_width + 1).max(2);
+    let height = options.height;
+    let span = (options.to - options.from).max(1);
+    let column = |t: i64| ((t - options.from) as f64 / span as f64 * (width - 1) as f64).round() as i64;
+    let row = |v: f64| ((max - v) / (max - min) * (height - 1) as f64).round() as i64;
+    let mut grid = vec![vec![' '; width]; height];
+    let put = |grid: &mut Vec<Vec<char>>, c: i64, r: i64, ch: char| {
+        if 0 <= c && (c as usize) < width && 0 <= r && (r as usize) < height {
+            grid[r as usize][c as usize] = ch;
+        }
+    };
+    for (i, band) in bands.iter().filter(|b| b.stacked).enumerate() {
+        let area = glyphs.areas[i % glyphs.areas.len()];
+        let points = band
+            .lower
+            .iter()
+            .zip(band.upper.iter())
+            .map(|(&(t, lower), &(_, upper))| (column(t), lower, upper))
+            .collect::<Vec<_>>();
+        for (i, &(c0, lower0, upper0)) in points.iter().enumerate() {
+            let &(c1, lower1, upper1) = points.get(i + 1).unwrap_or(&(c0, lower0, upper0));
+            for c in c0..c1.max(c0 + 1) {
+                let ratio = if c1 > c0 { (c - c0) as f64 / (c1 - c0) as f64 } else { 0.0 };
+                let (lower, upper) = (lower0 + (lower1 - lower0) * ratio, upper0 + (upper1 - upper0) * ratio);
+                for r in row(upper)..row(lower) + 1 {
+                    put(&mut grid, c, r, area);
+                }
+            }
+        }
+    }
+    for (i, band) in bands.iter().enumerate() {
+        let marker = glyphs.markers[i % glyphs.markers.len()];
+        let points = band.upper.iter().filter(|&&(_, v)| v.is_finite()).collect::<Vec<_>>();
+        for pair in points.windows(2) {
+            let (c0, r0, c1, r1) = (column(pair[0].0), row(pair[0].1), column(pair[1].0), row(pair[1].1));
+            for c in c0..c1 {
+                let r = r0 + ((r1 - r0) as f64 * (c - c0) as f64 / (c1 - c0) as f64).round() as i64;
+                put(&mut grid, c, r, marker);
+            }
+        }
+        for &&(t, v) in &points {
+            put(&mut grid, column(t), row(v), marker);
+        }
+    }
+    let mut output = String::new();
+    for (r, line) in grid.iter().enumerate() {
+        let label = if r == 0 {
+            &labels[0]
+        } else if r == (height - 1) / 2 && height > 2 {
+            &labels[1]
+        } else if r == height - 1 {
+            &labels[2]
+        } else {
+            ""
+        };
+        output.push_str(&format!(
+            "{:>w$}{}{}\n",
+            label,
+            glyphs.vertical,
+            line.iter().collect::<String>().trim_end(),
+            w = label_width
+        ));
+    }
+    output.push_str(&format!(
+        "{}{}{}\n",
+        " ".repeat(label_width),
+        glyphs.corner,
+        glyphs.horizontal.to_string().repeat(width)
+    ));
+    let (from_label, to_label) = (
+        format_time(options.from, span, &options.timezone),
+        format_time(options.to, span, &options.timezone),
+    );
+    output.push_str(&format!(
+        "{}{}{:>w$}\n",
+        " ".repeat(label_width + 1),
+        from_label,
+        to_label,
+        w = width.saturating_sub(from_label.len()).max(to_label.len() + 1)
+    ));
+    let mut areas = glyphs.areas.iter().cycle();
+    for (i, band) in bands.iter().enumerate() {
+        let glyph = if band.stacked { *areas.next().unwrap() } else { glyphs.markers[i % glyphs.markers.len()] };
+        output.push_str(&format!("{} {}\n", glyph, band.name));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use source::{Point, Series};
+
+    fn evaluated(name: &str, values: &[f64], stacked: bool) -> Evaluated {
+        Evaluated {
+            series: Series {
+                name: name.to_string(),
+                points: values.iter().enumerate().map(|(i, &value)| Point { time: i as i64 * 60, value }).collect(),
+            },
+            stacked,
+        }
+    }
+
+    #[test]
+    fn test_format_value() {
+        assert_eq!(format_value(0.0), "0");
+        assert_eq!(format_value(1.5), "1.5");
+        assert_eq!(format_value(-0.001), "0");
+        assert_eq!(format_value(2000000000000.0), "2T");
+        assert_eq!(format_value(1234.0), "1.23k");
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(1500000000, 3600, &Tz::UTC), "02:40");
+        assert_eq!(format_time(1500000000, 3600, &Tz::Asia__Tokyo), "11:40");
+        assert_eq!(format_time(1500000000, 7 * 86400, &Tz::UTC), "07-14");
+    }
+
+    #[test]
+    fn test_plot() {
+        let options = PlotOptions { width: 16, height: 3, from: 0, to: 180, ascii: true, timezone: Tz::UTC };
+        assert_eq!(
+            plot(&[evaluated("loadavg5", &[0.0, 1.0, 2.0, 2.0], false)], &options),
+            Ok("2|       *******\n1|  *****\n0|**\n +--------------\n  00:00    00:03\n* loadavg5\n".to_string())
+        );
+        assert_eq!(plot(&[], &options), Ok("no data\n".to_string()));
+        let options = PlotOptions { height: 0, ..options };
+        assert_eq!(plot(&[], &options), Err("height must be at least 2 rows: 0".to_string()));
+    }
+
+    #[test]
+    fn test_plot_stacked() {
+        let options = PlotOptions { width: 16, height: 5, from: 0, to: 60, ascii: true, timezone: Tz::UTC };
+        assert_eq!(
+            plot(&[evaluated("a", &[1.0, 1.0], true), evaluated("b", &[1.0, 3.0], true)], &options).unwrap(),
+            "4|          ++++\n |    ++++++====\n2|++++==========\n |**************\n0|##############\n +--------------\n  00:00    00:01\n# a\n= b\n"
+        );
+    }
+
+    #[test]
+    fn test_plot_legend() {
+        let options = PlotOptions { width: 16, height: 3, from: 0, to: 60, ascii: false, timezone: Tz::UTC };
+        let evaluated = [evaluated("a", &[1.0, 1.0], true), evaluated("b", &[1.0, 1.0], false), evaluated("c", &[1.0, 1.0], true)];
+        assert!(plot(&evaluated, &options).unwrap().ends_with("█ a\n◆ b\n▓ c\n"));
+        let options = PlotOptions { ascii: true, ..options };
+        assert!(plot(&evaluated, &options).unwrap().ends_with("# a\n+ b\n= c\n"));
+    }
+}
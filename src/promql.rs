@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+
+use serde_json::{self, Value};
+use serde_yaml;
+
+use functions;
+use {Argument, Duration, Factor, Metric};
+
+pub const LOSSY_CONVERSIONS: &[(&str, &str)] = &[
+    ("roleSlots", "roleSlots is converted to the selector of the role"),
+    ("product", "product is approximated by exp(sum(ln(...))), which fails for non-positive values"),
+    ("diff", "diff matches the series by their labels; add on() or ignoring() if they differ"),
+    ("divide", "divide matches the series by their labels; add on() or ignoring() if they differ"),
+    ("linearRegression", "linearRegression is converted to predict_linear, which gives the fitted value at each time"),
+    ("timeLeftForecast", "timeLeftForecast is approximated with predict_linear and deriv in seconds"),
+    ("group", "group is converted to or, which drops the series with the same labels"),
+    ("stack", "stack has no PromQL equivalent and is dropped"),
+];
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LabelMapping {
+    pub host: String,
+    pub service: String,
+    pub role: String,
+    pub metrics: BTreeMap<String, String>,
+}
+
+impl Default for LabelMapping {
+    fn default() -> LabelMapping {
+        LabelMapping {
+            host: "host".to_string(),
+            service: "service".to_string(),
+            role: "role".to_string(),
+            metrics: BTreeMap::new(),
+        }
+    }
+}
+
+impl LabelMapping {
+    pub fn from_json(src: &str) -> Result<LabelMapping, String> {
+        LabelMapping::from_value(&serde_json::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_yaml(src: &str) -> Result<LabelMapping, String> {
+        LabelMapping::from_value(&serde_yaml::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_value(value: &Value) -> Result<LabelMapping, String> {
+        let mut mapping = LabelMapping::default();
+        for (key, label) in [("host", &mut mapping.host), ("service", &mut mapping.service), ("role", &mut mapping.role)] {
+            match value.get(key) {
+                Some(Value::String(name)) => *label = name.clone(),
+                Some(_) => return Err(format!("expected a string for {}", key)),
+                None => {}
+            }
+        }
+        match value.get("metrics") {
+            Some(Value::Object(metrics)) => {
+                for (name, promql_name) in metrics {
+                    let promql_name = promql_name.as_str().ok_or_else(|| format!("expected a string for {}", name))?;
+                    mapping.metrics.insert(name.clone(), promql_name.to_string());
+                }
+            }
+            Some(_) => return Err("expected an object for metrics".to_string()),
+            None => {}
+        }
+        Ok(mapping)
+    }
+
+    fn selector(&self, metric_name: &str, labels: &[(&str, &str)], offset: Option<i64>) -> String {
+        let mut matchers = Vec::new();
+        let name = match self.metrics.get(metric_name) {
+            Some(name) => name.clone(),
+            None => metric_name.replace(['.', '-'], "_"),
+        };
+        let name = if name.contains('*') {
+            matchers.push(format!("__name__=~{}", promql_string(&name.replace('*', "[^_]*"))));
+            String::new()
+        } else if !is_metric_identifier(&name) {
+            matchers.push(format!("__name__={}", promql_string(&name)));
+            String::new()
+        } else {
+            name
+        };
+        for &(label, value) in labels {
+            matchers.push(format!("{}={}", label, promql_string(value)));
+        }
+        let offset = offset.map(|offset| format!(" offset {}", promql_seconds(offset))).unwrap_or_default();
+        format!("{}{{{}}}{}", name, matchers.join(", "), offset)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Conversion {
+    pub query: String,
+    pub warnings: Vec<String>,
+}
+
+fn promql_string(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t");
+    format!("\"{}\"", escaped)
+}
+
+fn is_metric_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+}
+
+fn promql_seconds(seconds: i64) -> String {
+    let units = [("y", 365 * 86400), ("w", 7 * 86400), ("d", 86400), ("h", 3600), ("m", 60)];
+    match units.iter().find(|&&(_, unit)| seconds % unit == 0) {
+        Some(&(suffix, unit)) => format!("{}{}", seconds / unit, suffix),
+        None => format!("{}s", seconds),
+    }
+}
+
+fn promql_factor(factor: &Factor) -> String {
+    match *factor {
+        Factor::Double(ref s) => s.clone(),
+        Factor::Fraction(ref nume, ref deno) => format!("({} / {})", nume, deno),
+    }
+}
+
+fn promql_duration(duration: &Duration) -> String {
+    let Duration(ref s) = *duration;
+    if s.ends_with("mo") {
+        format!("{}d", duration.seconds() / 86400)
+    } else {
+        s.clone()
+    }
+}
+
+fn is_selector(metric: &Metric) -> bool {
+    match *metric {
+        Metric::Host(..) | Metric::Service(..) | Metric::Role(..) | Metric::RoleSlot(..) => true,
+        Metric::TimeShift(ref metric, _) => is_selector(metric),
+        _ => false,
+    }
+}
+
+fn range(query: String, duration: &Duration, selector: bool) -> String {
+    if selector {
+        match query.find(" offset ") {
+            Some(i) => format!("{}[{}]{}", &query[..i], promql_duration(duration), &query[i..]),
+            None => format!("{}[{}]", query, promql_duration(duration)),
+        }
+    } else {
+        format!("({})[{}:]", query, promql_duration(duration))
+    }
+}
+
+struct Converter<'a> {
+    mapping: &'a LabelMapping,
+    warnings: Vec<String>,
+}
+
+impl<'a> Converter<'a> {
+    fn warn(&mut self, function: &str) {
+        if let Some(&(_, message)) = LOSSY_CONVERSIONS.iter().find(|&&(name, _)| name == function) {
+            if !self.warnings.iter().any(|warning| warning == message) {
+                self.warnings.push(message.to_string());
+            }
+        }
+    }
+
+    fn convert(&mut self, metric: &Metric, offset: Option<i64>) -> String {
+        self.warn(metric.function_name());
+        let mapping = self.mapping;
+        match *metric {
+            Metric::Host(ref host_id, ref metric_name) => {
+                mapping.selector(metric_name, &[(&mapping.host, host_id)], offset)
+            }
+            Metric::Service(ref service_name, ref metric_name) => {
+                mapping.selector(metric_name, &[(&mapping.service, service_name)], offset)
+            }
+            Metric::Role(ref service_name, ref role_name, ref metric_name)
+            | Metric::RoleSlot(ref service_name, ref role_name, ref metric_name) => mapping.selector(
+                metric_name,
+                &[(&mapping.service, service_name), (&mapping.role, role_name)],
+                offset,
+            ),
+            Metric::Avg(ref metric) => format!("avg({})", self.convert(metric, offset)),
+            Metric::Max(ref metric) => format!("max({})", self.convert(metric, offset)),
+            Metric::Min(ref metric) => format!("min({})", self.convert(metric, offset)),
+            Metric::Sum(ref metric) => format!("sum({})", self.convert(metric, offset)),
+            Metric::Product(ref metric) => format!("exp(sum(ln({})))", self.convert(metric, offset)),
+            Metric::Diff(ref metric1, ref metric2) => {
+                format!("({} - {})", self.convert(metric1, offset), self.convert(metric2, offset))
+            }
+            Metric::Divide(ref metric1, ref metric2) => {
+                format!("({} / {})", self.convert(metric1, offset), self.convert(metric2, offset))
+            }
+            Metric::Scale(ref metric, ref factor) => {
+                format!("({} * {})", self.convert(metric, offset), promql_factor(factor))
+            }
+            Metric::Offset(ref metric, ref factor) => {
+                format!("({} + {})", self.convert(metric, offset), promql_factor(factor))
+            }
+            Metric::Percentile(ref metric, ref percentage) => {
+                format!("quantile({}, {})", percentage.value() / 100.0, self.convert(metric, offset))
+            }
+            Metric::TimeShift(ref metric, ref duration) => {
+                self.convert(metric, Some(offset.unwrap_or(0) + duration.seconds()))
+            }
+            Metric::MovingAverage(ref metric, ref duration) => {
+                let query = self.convert(metric, offset);
+                format!("avg_over_time({})", range(query, duration, is_selector(metric)))
+            }
+            Metric::LinearRegression(ref metric, ref duration) => {
+                let query = self.convert(metric, offset);
+                format!("predict_linear({}, 0)", range(query, duration, is_selector(metric)))
+            }
+            Metric::TimeLeftForecast(ref metric, ref duration, ref threshold) => {
+                let query = range(self.convert(metric, offset), duration, is_selector(metric));
+                format!(
+                    "(({} - predict_linear({}, 0)) / deriv({}))",
+                    promql_factor(threshold),
+                    query,
+                    query
+                )
+            }
+            Metric::Group(ref metrics) => {
+                let queries = metrics.iter().map(|metric| self.convert(metric, offset)).collect::<Vec<_>>();
+                if queries.len() == 1 {
+                    queries.join("")
+                } else {
+                    format!("({})", queries.join(" or "))
+                }
+            }
+            Metric::Stack(ref metric) => self.convert(metric, offset),
+            Metric::Alias(ref metric, ref display_name) => format!(
+                "label_replace({}, \"alias\", {}, \"\", \"\")",
+                self.convert(metric, offset),
+                promql_string(display_name)
+            ),
+            Metric::Call { ref name, ref args } => {
+                let warning = match functions::function(name) {
+                    Some(_) => format!("{} has no PromQL conversion and is passed through as is", name),
+                    None => format!("{} is not a known function and is passed through as is", name),
+                };
+                if !self.warnings.contains(&warning) {
+                    self.warnings.push(warning);
+                }
+                let args = args
+                    .iter()
+                    .map(|arg| match *arg {
+                        Argument::Metric(ref metric) => self.convert(metric, offset),
+                        Argument::Factor(ref factor) => promql_factor(factor),
+                        Argument::Duration(ref duration) => promql_duration(duration),
+                        Argument::String(ref s) => promql_string(s),
+                    })
+                    .collect::<Vec<_>>();
+                format!("{}({})", name, args.join(", "))
+            }
+        }
+    }
+}
+
+pub fn to_promql(metric: &Metric, mapping: &LabelMapping) -> Conversion {
+    let mut converter = Converter { mapping, warnings: Vec::new() };
+    let query = converter.convert(metric, None);
+    Conversion { query, warnings: converter.warnings }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_metric;
+
+    fn promql(src: &str, mapping: &LabelMapping) -> Conversion {
+        to_promql(&parse_metric(src).unwrap(), mapping)
+    }
+
+    #[test]
+    fn test_to_promql() {
+        let mapping = LabelMapping::default();
+        assert_eq!(
+            promql("scale(divide(service(Blog, custom.errors), service(Blog, custom.requests)), 100)", &mapping),
+            Conversion {
+                query: "((custom_errors{service=\"Blog\"} / custom_requests{service=\"Blog\"}) * 100)".to_string(),
+                warnings: vec![LOSSY_CONVERSIONS[3].1.to_string()],
+            }
+        );
+        assert_eq!(
+            promql("alias(timeShift(avg(role(Blog:db, loadavg5)), 1w), last week)", &mapping).query,
+            "label_replace(avg(loadavg5{service=\"Blog\", role=\"db\"} offset 1w), \"alias\", \"last week\", \"\", \"\")"
+        );
+        assert_eq!(
+            promql("movingAverage(timeShift(host(22CXRB3pZmu, memory.*), 1mo), 1h)", &mapping).query,
+            "avg_over_time({__name__=~\"memory_[^_]*\", host=\"22CXRB3pZmu\"}[1h] offset 30d)"
+        );
+        assert_eq!(
+            promql("linearRegression(sum(roleSlots(Blog:db, loadavg5)), 1d)", &mapping),
+            Conversion {
+                query: "predict_linear((sum(loadavg5{service=\"Blog\", role=\"db\"}))[1d:], 0)".to_string(),
+                warnings: vec![LOSSY_CONVERSIONS[4].1.to_string(), LOSSY_CONVERSIONS[0].1.to_string()],
+            }
+        );
+        assert_eq!(
+            promql("stack(group(host(22CXRB3pZmu, loadavg5), host(3NpZbVH2B6f, loadavg5)))", &mapping).warnings,
+            vec![LOSSY_CONVERSIONS[7].1.to_string(), LOSSY_CONVERSIONS[6].1.to_string()]
+        );
+        assert_eq!(
+            promql("clamp_min(host(22CXRB3pZmu, loadavg5), 1/2, 5m, 'a b')", &mapping),
+            Conversion {
+                query: "clamp_min(loadavg5{host=\"22CXRB3pZmu\"}, (1 / 2), 5m, \"a b\")".to_string(),
+                warnings: vec!["clamp_min is not a known function and is passed through as is".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_offsets_and_names() {
+        let mapping = LabelMapping::default();
+        assert_eq!(
+            promql("timeShift(timeShift(host(a, b), 1d), 1w)", &mapping).query,
+            "b{host=\"a\"} offset 8d"
+        );
+        assert_eq!(
+            promql("timeShift(timeShift(host(a, b), 2h), 1w)", &mapping).query,
+            "b{host=\"a\"} offset 170h"
+        );
+        assert_eq!(
+            promql("host(a, 5xx.count-total)", &mapping).query,
+            "{__name__=\"5xx_count_total\", host=\"a\"}"
+        );
+        let mapping = LabelMapping::from_yaml("metrics:\n  loadavg5: 'node:load5'\n  uptime: 1-uptime\n").unwrap();
+        assert_eq!(promql("host(a, loadavg5)", &mapping).query, "node:load5{host=\"a\"}");
+        assert_eq!(promql("host(a, uptime)", &mapping).query, "{__name__=\"1-uptime\", host=\"a\"}");
+    }
+
+    #[test]
+    fn test_promql_string() {
+        assert_eq!(promql_string("last week"), "\"last week\"");
+        assert_eq!(promql_string("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(promql_string("C:\\logs\\"), "\"C:\\\\logs\\\\\"");
+        assert_eq!(promql_string("ロード\tアベレージ\n"), "\"ロード\\tアベレージ\\n\"");
+        assert_eq!(
+            promql("alias(host(a, b), 'ロード \"5\" \\ avg')", &LabelMapping::default()).query,
+            "label_replace(b{host=\"a\"}, \"alias\", \"ロード \\\"5\\\" \\\\ avg\", \"\", \"\")"
+        );
+    }
+
+    #[test]
+    fn test_label_mapping() {
+        let mapping = LabelMapping::from_yaml("host: instance\nmetrics:\n  loadavg5: node_load5\n").unwrap();
+        assert_eq!(
+            promql("host(22CXRB3pZmu, loadavg5)", &mapping).query,
+            "node_load5{instance=\"22CXRB3pZmu\"}"
+        );
+        assert!(LabelMapping::from_json("{\"metrics\": []}").is_err());
+    }
+}
@@ -0,0 +1,377 @@
+use pest::Parser;
+
+use functions::{self, Function, Kind};
+use suggest::{did_you_mean, suggest_function};
+use {check_depth, convert_literal, Argument, Metric, MetricParser, ParseError, Rule, Span};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PartialMetric {
+    Metric(Metric, Span),
+    Call(String, Vec<PartialMetric>, Span),
+    Argument(String, Span),
+    Error(Span),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Recovery {
+    pub metric: PartialMetric,
+    pub errors: Vec<ParseError>,
+    pub warnings: Vec<ParseError>,
+}
+
+enum Raw {
+    Call(Span, Vec<Raw>, Span),
+    Literal(Span),
+    Empty(Span),
+}
+
+struct Recoverer<'a> {
+    src: &'a str,
+    pos: usize,
+    syntax_errors: Vec<ParseError>,
+    errors: Vec<ParseError>,
+    warnings: Vec<ParseError>,
+    hints: Vec<ParseError>,
+}
+
+impl<'a> Recoverer<'a> {
+    fn new(src: &'a str) -> Recoverer<'a> {
+        Recoverer {
+            src,
+            pos: 0,
+            syntax_errors: Vec::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            hints: Vec::new(),
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\r', '\n']).len();
+    }
+
+    fn argument(&mut self) -> Raw {
+        self.skip_whitespace();
+        let start = self.pos;
+        let name_len = self.rest().find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(self.rest().len());
+        if name_len > 0 {
+            self.pos += name_len;
+            self.skip_whitespace();
+            if self.rest().starts_with('(') {
+                return self.call(Span { start, end: start + name_len });
+            }
+            self.pos = start;
+        }
+        let mut depth = 0;
+        while let Some(c) = self.rest().chars().next() {
+            match c {
+                ',' | ')' if depth == 0 => break,
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                '\'' | '"' => match self.rest()[1..].find(c) {
+                    Some(i) => self.pos += i + 1,
+                    None => {
+                        let message = "unterminated quote".to_string();
+                        let span = Span { start: self.pos, end: self.pos + 1 };
+                        self.hints.push(ParseError { span, message: message.clone() });
+                        self.syntax_errors.push(ParseError { span, message });
+                    }
+                },
+                _ => {}
+            }
+            self.pos += c.len_utf8();
+        }
+        let end = start + self.src[start..self.pos].trim_end().len();
+        if start == end {
+            Raw::Empty(Span { start, end })
+        } else {
+            Raw::Literal(Span { start, end })
+        }
+    }
+
+    fn call(&mut self, name: Span) -> Raw {
+        self.pos += 1;
+        let mut args = Vec::new();
+        self.skip_whitespace();
+        if self.rest().starts_with(')') {
+            self.pos += 1;
+            return Raw::Call(name, args, Span { start: name.start, end: self.pos });
+        }
+        loop {
+            args.push(self.argument());
+            self.skip_whitespace();
+            match self.rest().chars().next() {
+                Some(',') => self.pos += 1,
+                Some(')') => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => {
+                    let span = Span { start: self.pos, end: self.pos };
+                    self.syntax_errors.push(ParseError { span, message: "expected )".to_string() });
+                    break;
+                }
+            }
+        }
+        Raw::Call(name, args, Span { start: name.start, end: self.pos })
+    }
+
+    fn has_syntax_error(&self, span: Span) -> bool {
+        self.syntax_errors.iter().any(|err| span.start <= err.span.start && err.span.start <= span.end)
+    }
+
+    fn error(&mut self, span: Span, message: String) -> PartialMetric {
+        self.errors.push(ParseError { span, message });
+        PartialMetric::Error(span)
+    }
+
+    fn convert(&mut self, raw: &Raw, kind: Option<Kind>) -> PartialMetric {
+        match *raw {
+            Raw::Empty(span) => {
+                let describe = kind.map_or("argument", Kind::describe);
+                self.error(span, format!("missing {}", describe))
+            }
+            Raw::Literal(span) if self.has_syntax_error(span) => PartialMetric::Error(span),
+            Raw::Literal(span) => {
+                let text = &self.src[span.start..span.end];
+                match kind {
+                    Some(kind) if !kind.rule().is_some_and(|rule| matches(rule, text)) => {
+                        let message = kind.invalid(text);
+                        if kind == Kind::Duration {
+                            self.hints.push(ParseError { span, message: message.clone() });
+                        }
+                        self.error(span, message)
+                    }
+                    _ => PartialMetric::Argument(text.to_string(), span),
+                }
+            }
+            Raw::Call(_, _, span) if kind.is_some_and(|kind| kind != Kind::Metrics) => {
+                let text = &self.src[span.start..span.end];
+                self.error(span, kind.map_or(String::new(), |kind| kind.invalid(text)))
+            }
+            Raw::Call(name_span, ref args, span) => {
+                let errors = self.errors.len();
+                let name = &self.src[name_span.start..name_span.end];
+                let function = functions::function(name);
+                match function {
+                    Some(function) => {
+                        if let Err(message) = function.check_arity(args.len()) {
+                            self.errors.push(ParseError { span: name_span, message });
+                        }
+                    }
+                    None => {
+                        let message = format!("unknown function: {}{}", name, did_you_mean(suggest_function(name)));
+                        self.warnings.push(ParseError { span: name_span, message });
+                    }
+                }
+                let children = args
+                    .iter()
+                    .enumerate()
+                    .map(|(i, arg)| self.convert(arg, function.and_then(|function| function.kind(i))))
+                    .collect::<Vec<_>>();
+                if self.errors.len() == errors && !self.has_syntax_error(span) {
+                    return self.metric(name_span, function, children, span);
+                }
+                PartialMetric::Call(name.to_string(), children, span)
+            }
+        }
+    }
+
+    fn metric(
+        &mut self,
+        name_span: Span,
+        function: Option<&Function>,
+        children: Vec<PartialMetric>,
+        span: Span,
+    ) -> PartialMetric {
+        let name = &self.src[name_span.start..name_span.end];
+        if !matches(Rule::function_name, name) {
+            self.errors.push(ParseError { span: name_span, message: format!("invalid function name: {}", name) });
+            return PartialMetric::Call(name.to_string(), children, span);
+        }
+        let mut literals = Vec::new();
+        for (i, child) in children.iter().enumerate() {
+            match *child {
+                PartialMetric::Metric(..) => {}
+                PartialMetric::Argument(ref text, argument_span) => {
+                    let argument = match function.and_then(|function| function.kind(i)) {
+                        Some(kind) => convert_literal(kind, text).ok(),
+                        None => [Kind::Duration, Kind::Factor, Kind::DisplayName]
+                            .iter()
+                            .find_map(|&kind| convert_literal(kind, text).ok()),
+                    };
+                    match argument {
+                        Some(argument) => literals.push(argument),
+                        None => {
+                            let message = format!("invalid argument: {}", text);
+                            self.errors.push(ParseError { span: argument_span, message });
+                            return PartialMetric::Call(name.to_string(), children, span);
+                        }
+                    }
+                }
+                PartialMetric::Call(..) | PartialMetric::Error(_) => {
+                    return PartialMetric::Call(name.to_string(), children, span);
+                }
+            }
+        }
+        let mut literals = literals.into_iter();
+        let args = children
+            .into_iter()
+            .filter_map(|child| match child {
+                PartialMetric::Metric(metric, _) => Some(Argument::Metric(metric)),
+                _ => literals.next(),
+            })
+            .collect();
+        match Metric::from_call(name, args) {
+            Some(metric) => PartialMetric::Metric(metric, span),
+            None => self.error(span, format!("invalid arguments to {}", name)),
+        }
+    }
+}
+
+pub(crate) fn matches(rule: Rule, s: &str) -> bool {
+    match MetricParser::parse_str(rule, s) {
+        Ok(mut pairs) => pairs.next().is_some_and(|pair| pair.as_str() == s),
+        Err(_) => false,
+    }
+}
+
+pub fn parse_recovering(src: &str) -> Recovery {
+    if let Err(err) = check_depth(src) {
+        let span = err.span;
+        return Recovery { metric: PartialMetric::Error(span), errors: vec![err], warnings: vec![] };
+    }
+    let mut recoverer = Recoverer::new(src);
+    let raw = recoverer.argument();
+    recoverer.skip_whitespace();
+    if recoverer.pos < src.len() {
+        let span = Span { start: recoverer.pos, end: src.len() };
+        let message = format!("unexpected {}", recoverer.rest().trim_end());
+        recoverer.syntax_errors.push(ParseError { span, message });
+    }
+    let metric = recoverer.convert(&raw, Some(Kind::Metrics));
+    let mut errors = recoverer.syntax_errors;
+    errors.extend(recoverer.errors);
+    errors.sort_by_key(|err| err.span.start);
+    Recovery { metric, errors, warnings: recoverer.warnings }
+}
+
+pub fn diagnose(src: &str) -> Option<ParseError> {
+    if check_depth(src).is_err() {
+        return None;
+    }
+    let mut recoverer = Recoverer::new(src);
+    let raw = recoverer.argument();
+    recoverer.convert(&raw, Some(Kind::Metrics));
+    recoverer.hints.into_iter().min_by_key(|err| err.span.start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {parse_metric, Argument};
+
+    fn messages(src: &str) -> Vec<(usize, String)> {
+        parse_recovering(src).errors.into_iter().map(|err| (err.span.start, err.message)).collect()
+    }
+
+    #[test]
+    fn test_parse_recovering() {
+        let src = "group(host(22CXRB3pZmu, loadavg5), avg(roleSlots(Blog:db, loadavg5)))";
+        let recovery = parse_recovering(src);
+        assert_eq!(recovery.errors, vec![]);
+        let span = Span { start: 0, end: src.len() };
+        assert_eq!(recovery.metric, PartialMetric::Metric(parse_metric(src).unwrap(), span));
+
+        let src = "group(avg(host(22CXRB3pZmu)), timeShift(host(22CXRB3pZmu, loadavg5), 1s), average(service(Blog, x)))";
+        let recovery = parse_recovering(src);
+        assert_eq!(
+            messages(src),
+            vec![
+                (10, "host expects 2 arguments but got 1".to_string()),
+                (69, "invalid duration: 1s".to_string()),
+            ]
+        );
+        assert_eq!(
+            recovery.warnings,
+            vec![ParseError { span: Span { start: 74, end: 81 }, message: "unknown function: average".to_string() }]
+        );
+        match recovery.metric {
+            PartialMetric::Call(ref name, ref children, _) => {
+                assert_eq!(name, "group");
+                assert_eq!(children.len(), 3);
+                assert_eq!(
+                    children[2],
+                    PartialMetric::Metric(
+                        Metric::Call {
+                            name: "average".to_string(),
+                            args: vec![Argument::Metric(Metric::Service("Blog".to_string(), "x".to_string()))],
+                        },
+                        Span { start: 74, end: 99 },
+                    )
+                );
+            }
+            ref metric => panic!("unexpected {:?}", metric),
+        }
+    }
+
+    #[test]
+    fn test_parse_recovering_unknown_arguments() {
+        let src = "group(custom(host(22CXRB3pZmu, loadavg5), 5m, 1/2, 'load avg'), host(22CXRB3pZmu, loadavg5))";
+        let recovery = parse_recovering(src);
+        assert_eq!(recovery.errors, vec![]);
+        let span = Span { start: 0, end: src.len() };
+        assert_eq!(recovery.metric, PartialMetric::Metric(parse_metric(src).unwrap(), span));
+    }
+
+    #[test]
+    fn test_parse_recovering_syntax_errors() {
+        assert_eq!(
+            messages("diff(avg(host(22CXRB3pZmu, loadavg5), host(22CXRB3pZmu, loadavg5))"),
+            vec![
+                (0, "diff expects 2 arguments but got 1".to_string()),
+                (5, "avg expects 1 arguments but got 2".to_string()),
+                (66, "expected )".to_string()),
+            ]
+        );
+        assert_eq!(
+            messages("alias(host(22CXRB3pZmu, loadavg5), 'load)"),
+            vec![(35, "unterminated quote".to_string())]
+        );
+        assert_eq!(
+            messages("scale(host(22CXRB3pZmu, loadavg5), , x)) foo"),
+            vec![
+                (0, "scale expects 2 arguments but got 3".to_string()),
+                (35, "missing factor".to_string()),
+                (39, "unexpected ) foo".to_string()),
+            ]
+        );
+        assert_eq!(messages("group()"), vec![(0, "group expects at least 1 arguments but got 0".to_string())]);
+        assert_eq!(messages("_group(host(22CXRB3pZmu, loadavg5))"), vec![(0, "invalid function name: _group".to_string())]);
+        assert_eq!(messages("diff(\n  avg(host(22CXRB3pZmu, loadavg5)) ,\n  host(22CXRB3pZmu, loadavg5)\n)\n"), vec![]);
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let src = "group(movingAvg(host(22CXRB3pZmu, loadavg5), 5m), timeShift(host(22CXRB3pZmu, loadavg5), 5hours))";
+        let recovery = parse_recovering(src);
+        assert_eq!(messages(src), vec![(89, "invalid duration: 5hours (did you mean 5h?)".to_string())]);
+        assert_eq!(
+            recovery.warnings,
+            vec![ParseError {
+                span: Span { start: 6, end: 15 },
+                message: "unknown function: movingAvg (did you mean movingAverage?)".to_string(),
+            }]
+        );
+        let err = diagnose("group(host(22CXRB3pZmu, loadavg5), timeShift(host(22CXRB3pZmu, loadavg5), 3mins))").unwrap();
+        assert_eq!((err.span.start, err.message.as_str()), (74, "invalid duration: 3mins (did you mean 3m?)"));
+        let err = diagnose("timeShift(host(22CXRB3pZmu, loadavg5), 1s)").unwrap();
+        assert_eq!((err.span.start, err.message.as_str()), (39, "invalid duration: 1s"));
+        assert_eq!(diagnose("avg(host(22CXRB3pZmu)"), None);
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::BTreeSet;
+
+use serde_json::Value;
+
+use functions::{self, Kind};
+use {Argument, Metric};
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct References {
+    pub hosts: BTreeSet<String>,
+    pub services: BTreeSet<String>,
+    pub roles: BTreeSet<(String, String)>,
+    pub metric_names: BTreeSet<String>,
+    pub functions: BTreeSet<String>,
+}
+
+impl References {
+    pub fn merge(&mut self, other: References) {
+        self.hosts.extend(other.hosts);
+        self.services.extend(other.services);
+        self.roles.extend(other.roles);
+        self.metric_names.extend(other.metric_names);
+        self.functions.extend(other.functions);
+    }
+
+    pub fn role_names(&self) -> Vec<String> {
+        self.roles.iter().map(|(service, role)| format!("{}:{}", service, role)).collect()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "hosts": self.hosts,
+            "services": self.services,
+            "roles": self.role_names(),
+            "metricNames": self.metric_names,
+            "functions": self.functions,
+        })
+    }
+}
+
+pub fn references(metric: &Metric) -> References {
+    let mut refs = References::default();
+    collect(metric.clone(), &mut refs);
+    refs
+}
+
+fn collect(metric: Metric, refs: &mut References) {
+    let function = functions::function(metric.function_name());
+    let (name, args) = metric.into_call();
+    refs.functions.insert(name);
+    for (i, arg) in args.into_iter().enumerate() {
+        match (arg, function.and_then(|function| function.kind(i))) {
+            (Argument::Metric(metric), _) => collect(metric, refs),
+            (Argument::String(host_id), Some(Kind::HostId)) => {
+                refs.hosts.insert(host_id);
+            }
+            (Argument::String(service_name), Some(Kind::ServiceName)) => {
+                refs.services.insert(service_name);
+            }
+            (Argument::String(role_full_name), Some(Kind::RoleFullName)) => {
+                if let Some(i) = role_full_name.find(':') {
+                    let (service_name, role_name) = (&role_full_name[..i], &role_full_name[i + 1..]);
+                    refs.services.insert(service_name.to_string());
+                    refs.roles.insert((service_name.to_string(), role_name.to_string()));
+                }
+            }
+            (Argument::String(metric_name), Some(Kind::MetricName)) => {
+                refs.metric_names.insert(metric_name);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_metric;
+
+    fn set(values: &[&str]) -> BTreeSet<String> {
+        values.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_references() {
+        let metric = parse_metric(
+            "group(alias(host(22CXRB3pZmu, loadavg5), load), avg(roleSlots(Blog:db, memory.*)), \
+             divide(service(Blog, access_count), role(Blog:db, loadavg5)), host(22CXRB3pZmu, loadavg5))",
+        ).unwrap();
+        let refs = references(&metric);
+        assert_eq!(refs.hosts, set(&["22CXRB3pZmu"]));
+        assert_eq!(refs.services, set(&["Blog"]));
+        assert_eq!(refs.role_names(), vec!["Blog:db"]);
+        assert_eq!(refs.metric_names, set(&["access_count", "loadavg5", "memory.*"]));
+        assert_eq!(
+            refs.functions,
+            set(&["alias", "avg", "divide", "group", "host", "role", "roleSlots", "service"])
+        );
+        assert_eq!(
+            refs.to_json().to_string(),
+            r#"{"functions":["alias","avg","divide","group","host","role","roleSlots","service"],"hosts":["22CXRB3pZmu"],"metricNames":["access_count","loadavg5","memory.*"],"roles":["Blog:db"],"services":["Blog"]}"#
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use functions::{self, Kind};
+use recover::matches;
+use {Argument, Metric, Rule};
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Renames {
+    pub hosts: Vec<(String, String)>,
+    pub services: Vec<(String, String)>,
+    pub roles: Vec<((String, String), (String, String))>,
+    pub metrics: Vec<(String, String)>,
+    pub aliases: bool,
+}
+
+impl Renames {
+    pub fn add_host(&mut self, spec: &str) -> Result<(), String> {
+        let (old, new) = split_spec(spec)?;
+        check_name(Kind::HostId, Rule::host_id, &new)?;
+        self.hosts.push((old, new));
+        Ok(())
+    }
+
+    pub fn add_service(&mut self, spec: &str) -> Result<(), String> {
+        let (old, new) = split_spec(spec)?;
+        check_name(Kind::ServiceName, Rule::service_name, &new)?;
+        self.services.push((old, new));
+        Ok(())
+    }
+
+    pub fn add_role(&mut self, spec: &str) -> Result<(), String> {
+        let (old, new) = split_spec(spec)?;
+        let (new_service, new_role) = split_role(&new)?;
+        check_name(Kind::ServiceName, Rule::service_name, &new_service)?;
+        check_name(Kind::RoleFullName, Rule::role_name, &new_role)?;
+        self.roles.push((split_role(&old)?, (new_service, new_role)));
+        Ok(())
+    }
+
+    pub fn add_metric(&mut self, spec: &str) -> Result<(), String> {
+        let (pattern, replacement) = split_spec(spec)?;
+        if pattern.matches('*').count() < replacement.matches('*').count() {
+            return Err(format!("replacement has more wildcards than the pattern: {}", spec));
+        }
+        check_name(Kind::MetricName, Rule::metric_name, &replacement)?;
+        self.metrics.push((pattern, replacement));
+        Ok(())
+    }
+
+    fn host(&self, host_id: String) -> String {
+        lookup(&self.hosts, host_id)
+    }
+
+    fn service(&self, service_name: String) -> String {
+        lookup(&self.services, service_name)
+    }
+
+    fn role(&self, service_name: String, role_name: String) -> (String, String) {
+        match self.roles.iter().find(|&&((ref s, ref r), _)| *s == service_name && *r == role_name) {
+            // A role mapping that keeps its service still follows the service rename.
+            Some((_, (new_service, new_role))) if *new_service == service_name => {
+                (self.service(service_name), new_role.clone())
+            }
+            Some((_, new)) => new.clone(),
+            None => (self.service(service_name), role_name),
+        }
+    }
+
+    fn metric(&self, metric_name: String) -> String {
+        for (pattern, replacement) in &self.metrics {
+            if let Some(captures) = capture(pattern, &metric_name) {
+                let mut parts = replacement.split('*');
+                let mut renamed = parts.next().unwrap_or("").to_string();
+                for (part, captured) in parts.zip(captures) {
+                    renamed.push_str(captured);
+                    renamed.push_str(part);
+                }
+                return renamed;
+            }
+        }
+        metric_name
+    }
+
+    fn display_name(&self, display_name: String) -> String {
+        if !self.aliases {
+            return display_name;
+        }
+        let mut pairs = self
+            .roles
+            .iter()
+            .map(|&((ref old_service, ref old_role), _)| {
+                let (new_service, new_role) = self.role(old_service.clone(), old_role.clone());
+                (format!("{}:{}", old_service, old_role), format!("{}:{}", new_service, new_role))
+            })
+            .collect::<Vec<_>>();
+        pairs.extend(self.hosts.iter().chain(self.services.iter()).cloned());
+        let mut renamed = String::new();
+        let mut i = 0;
+        while let Some(c) = display_name[i..].chars().next() {
+            let rest = &display_name[i..];
+            let found = pairs
+                .iter()
+                .filter(|(old, _)| {
+                    rest.starts_with(old.as_str())
+                        && !display_name[..i].chars().next_back().is_some_and(is_name_char)
+                        && !rest[old.len()..].chars().next().is_some_and(is_name_char)
+                })
+                .max_by_key(|(old, _)| old.len());
+            match found {
+                Some((old, new)) => {
+                    renamed.push_str(new);
+                    i += old.len();
+                }
+                None => {
+                    renamed.push(c);
+                    i += c.len_utf8();
+                }
+            }
+        }
+        renamed
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+fn check_name(kind: Kind, rule: Rule, name: &str) -> Result<(), String> {
+    if matches(rule, name) {
+        Ok(())
+    } else {
+        Err(kind.invalid(name))
+    }
+}
+
+fn split_spec(spec: &str) -> Result<(String, String), String> {
+    match spec.find('=') {
+        Some(i) if i > 0 && i + 1 < spec.len() => Ok((spec[..i].to_string(), spec[i + 1..].to_string())),
+        _ => Err(format!("expected OLD=NEW: {}", spec)),
+    }
+}
+
+fn split_role(role: &str) -> Result<(String, String), String> {
+    match role.find(':') {
+        Some(i) if i > 0 && i + 1 < role.len() => Ok((role[..i].to_string(), role[i + 1..].to_string())),
+        _ => Err(format!("expected Service:role: {}", role)),
+    }
+}
+
+fn lookup(pairs: &[(String, String)], name: String) -> String {
+    match pairs.iter().find(|(old, _)| *old == name) {
+        Some((_, new)) => new.clone(),
+        None => name,
+    }
+}
+
+fn capture<'a>(pattern: &str, name: &'a str) -> Option<Vec<&'a str>> {
+    match pattern.find('*') {
+        None => if pattern == name { Some(Vec::new()) } else { None },
+        Some(i) => {
+            if !name.starts_with(&pattern[..i]) {
+                return None;
+            }
+            let rest = &pattern[i + 1..];
+            for j in i..name.len() + 1 {
+                if !name.is_char_boundary(j) {
+                    continue;
+                }
+                if let Some(mut captures) = capture(rest, &name[j..]) {
+                    captures.insert(0, &name[i..j]);
+                    return Some(captures);
+                }
+            }
+            None
+        }
+    }
+}
+
+pub fn rename(metric: Metric, renames: &Renames) -> Metric {
+    let function = functions::function(metric.function_name());
+    let (name, args) = metric.into_call();
+    let args = args
+        .into_iter()
+        .enumerate()
+        .map(|(i, arg)| match (arg, function.and_then(|function| function.kind(i))) {
+            (Argument::Metric(metric), _) => Argument::Metric(rename(metric, renames)),
+            (Argument::String(host_id), Some(Kind::HostId)) => Argument::String(renames.host(host_id)),
+            (Argument::String(service_name), Some(Kind::ServiceName)) => {
+                Argument::String(renames.service(service_name))
+            }
+            (Argument::String(role_full_name), Some(Kind::RoleFullName)) => match role_full_name.find(':') {
+                Some(i) => {
+                    let role_name = role_full_name[i + 1..].to_string();
+                    let (service_name, role_name) = renames.role(role_full_name[..i].to_string(), role_name);
+                    Argument::String(format!("{}:{}", service_name, role_name))
+                }
+                None => Argument::String(role_full_name),
+            },
+            (Argument::String(metric_name), Some(Kind::MetricName)) => Argument::String(renames.metric(metric_name)),
+            (Argument::String(display_name), Some(Kind::DisplayName)) => {
+                Argument::String(renames.display_name(display_name))
+            }
+            (arg, _) => arg,
+        })
+        .collect::<Vec<_>>();
+    Metric::from_call(&name, args).expect("renaming keeps the type of every argument")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use {compact_print, parse_metric};
+
+    fn renamed(src: &str, renames: &Renames) -> String {
+        compact_print(rename(parse_metric(src).unwrap(), renames))
+    }
+
+    #[test]
+    fn test_capture() {
+        assert_eq!(capture("loadavg5", "loadavg5"), Some(vec![]));
+        assert_eq!(capture("loadavg5", "loadavg1"), None);
+        assert_eq!(capture("custom.*.used", "custom.disk.sda.used"), Some(vec!["disk.sda"]));
+        assert_eq!(capture("*.*", "a.b.c"), Some(vec!["a", "b.c"]));
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut renames = Renames::default();
+        renames.add_host("22CXRB3pZmu=3NpZbVH2B6f").unwrap();
+        renames.add_service("Blog=Diary").unwrap();
+        renames.add_role("Blog:db-slave=Blog:db-replica").unwrap();
+        renames.add_metric("custom.old.*=custom.new.*").unwrap();
+        assert_eq!(
+            renamed(
+                "group(host(22CXRB3pZmu, loadavg5), service(Blog, custom.old.count), role(Blog:db-slave, loadavg5), roleSlots(Blog:app, custom.old.*))",
+                &renames
+            ),
+            "group(host(3NpZbVH2B6f, loadavg5), service(Diary, custom.new.count), role(Diary:db-replica, loadavg5), roleSlots(Diary:app, custom.new.*))"
+        );
+        assert_eq!(
+            renamed("alias(host(22CXRB3pZmu, loadavg5), 'Blog:db-slave 22CXRB3pZmu')", &renames),
+            "alias(host(3NpZbVH2B6f, loadavg5), 'Blog:db-slave 22CXRB3pZmu')"
+        );
+        renames.aliases = true;
+        assert_eq!(
+            renamed("alias(host(22CXRB3pZmu, loadavg5), 'Blog:db-slave 22CXRB3pZmu')", &renames),
+            "alias(host(3NpZbVH2B6f, loadavg5), 'Diary:db-replica 3NpZbVH2B6f')"
+        );
+        assert_eq!(
+            renamed("alias(service(Blog, x), 'Blog Blogger Blog2 (Blog)')", &renames),
+            "alias(service(Diary, x), 'Diary Blogger Blog2 (Diary)')"
+        );
+        renames.add_role("Blog:web=Shop:front").unwrap();
+        assert_eq!(
+            renamed("group(service(Blog, x), role(Blog:db, x), role(Blog:db-slave, x), role(Blog:web, x))", &renames),
+            "group(service(Diary, x), role(Diary:db, x), role(Diary:db-replica, x), role(Shop:front, x))"
+        );
+        assert!(renames.add_host("22CXRB3pZmu").is_err());
+        assert_eq!(renames.add_host("a=b c"), Err("invalid host id: b c".to_string()));
+        assert_eq!(renames.add_service("Blog=Blog)"), Err("invalid service name: Blog)".to_string()));
+        assert!(renames.add_role("Blog:db=Blog:db slave").is_err());
+        assert!(renames.add_metric("loadavg5=load avg5").is_err());
+        assert!(renames.add_role("Blog=Diary").is_err());
+        assert!(renames.add_metric("loadavg5=loadavg.*").is_err());
+    }
+}
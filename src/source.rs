@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+
+use serde_json::{self, Value};
+use serde_yaml;
+
+use Metric;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Service {
+    pub name: String,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Host {
+    pub id: String,
+    pub name: String,
+    pub roles: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Point {
+    pub time: i64,
+    pub value: f64,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Series {
+    pub name: String,
+    pub points: Vec<Point>,
+}
+
+pub trait MetricSource {
+    fn services(&self) -> Result<Vec<Service>, String>;
+    fn hosts(&self, service: Option<&str>, role: Option<&str>) -> Result<Vec<Host>, String>;
+    fn host_metric_names(&self, host_id: &str) -> Result<Vec<String>, String>;
+    fn service_metric_names(&self, service_name: &str) -> Result<Vec<String>, String>;
+    fn host_metric_values(&self, host_id: &str, metric_name: &str, from: i64, to: i64) -> Result<Vec<Point>, String>;
+    fn service_metric_values(&self, service_name: &str, metric_name: &str, from: i64, to: i64) -> Result<Vec<Point>, String>;
+}
+
+pub fn services_from_json(value: &Value) -> Result<Vec<Service>, String> {
+    array(value, "services")?
+        .iter()
+        .map(|v| Ok(Service { name: string(v, "name")?, roles: strings(v, "roles")? }))
+        .collect()
+}
+
+pub fn hosts_from_json(value: &Value) -> Result<Vec<Host>, String> {
+    array(value, "hosts")?
+        .iter()
+        .map(|v| {
+            let mut roles = Vec::new();
+            if let Some(services) = v.get("roles").and_then(|r| r.as_object()) {
+                for (service_name, role_names) in services {
+                    for role_name in role_names.as_array().into_iter().flatten() {
+                        if let Some(role_name) = role_name.as_str() {
+                            roles.push((service_name.clone(), role_name.to_string()));
+                        }
+                    }
+                }
+            } else if v.get("roleFullnames").is_some() {
+                for role_full_name in strings(v, "roleFullnames")? {
+                    let mut names = role_full_name.splitn(2, ':');
+                    if let (Some(service_name), Some(role_name)) = (names.next(), names.next()) {
+                        roles.push((service_name.to_string(), role_name.to_string()));
+                    }
+                }
+            }
+            Ok(Host { id: string(v, "id")?, name: string(v, "name")?, roles })
+        })
+        .collect()
+}
+
+pub(crate) fn points_from_json(value: &Value) -> Result<Vec<Point>, String> {
+    value
+        .as_array()
+        .ok_or_else(|| format!("not an array: {}", value))?
+        .iter()
+        .map(|v| {
+            Ok(Point {
+                time: field(v, "time")?.as_i64().ok_or_else(|| format!("invalid time: {}", v))?,
+                value: field(v, "value")?.as_f64().ok_or_else(|| format!("invalid value: {}", v))?,
+            })
+        })
+        .collect()
+}
+
+pub(crate) fn field<'a>(value: &'a Value, key: &str) -> Result<&'a Value, String> {
+    value.get(key).ok_or_else(|| format!("missing field: {}", key))
+}
+
+pub(crate) fn array<'a>(value: &'a Value, key: &str) -> Result<&'a Vec<Value>, String> {
+    field(value, key)?.as_array().ok_or_else(|| format!("not an array: {}", key))
+}
+
+pub(crate) fn string(value: &Value, key: &str) -> Result<String, String> {
+    field(value, key)?
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| format!("not a string: {}", key))
+}
+
+pub(crate) fn strings(value: &Value, key: &str) -> Result<Vec<String>, String> {
+    array(value, key)?
+        .iter()
+        .map(|v| v.as_str().map(|s| s.to_string()).ok_or_else(|| format!("not a string: {}", v)))
+        .collect()
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct LocalSource {
+    pub services: Vec<Service>,
+    pub hosts: Vec<Host>,
+    pub host_metrics: BTreeMap<String, BTreeMap<String, Vec<Point>>>,
+    pub service_metrics: BTreeMap<String, BTreeMap<String, Vec<Point>>>,
+}
+
+impl LocalSource {
+    pub fn from_json(src: &str) -> Result<LocalSource, String> {
+        LocalSource::from_value(&serde_json::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_yaml(src: &str) -> Result<LocalSource, String> {
+        LocalSource::from_value(&serde_yaml::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_value(value: &Value) -> Result<LocalSource, String> {
+        Ok(LocalSource {
+            services: if value.get("services").is_some() { services_from_json(value)? } else { Vec::new() },
+            hosts: if value.get("hosts").is_some() { hosts_from_json(value)? } else { Vec::new() },
+            host_metrics: metrics_from_json(value, "hostMetrics")?,
+            service_metrics: metrics_from_json(value, "serviceMetrics")?,
+        })
+    }
+
+    pub fn time_range(&self) -> Option<(i64, i64)> {
+        let times = self
+            .host_metrics
+            .values()
+            .chain(self.service_metrics.values())
+            .flat_map(|metrics| metrics.values())
+            .flat_map(|points| points.iter().map(|p| p.time))
+            .collect::<Vec<_>>();
+        match (times.iter().min(), times.iter().max()) {
+            (Some(&from), Some(&to)) => Some((from, to)),
+            _ => None,
+        }
+    }
+}
+
+fn metrics_from_json(value: &Value, key: &str) -> Result<BTreeMap<String, BTreeMap<String, Vec<Point>>>, String> {
+    let mut metrics = BTreeMap::new();
+    if let Some(objects) = value.get(key) {
+        for (id, names) in objects.as_object().ok_or_else(|| format!("not an object: {}", key))? {
+            let mut points = BTreeMap::new();
+            for (name, values) in names.as_object().ok_or_else(|| format!("not an object: {}", id))? {
+                points.insert(name.clone(), points_from_json(values)?);
+            }
+            metrics.insert(id.clone(), points);
+        }
+    }
+    Ok(metrics)
+}
+
+fn points_between(points: Option<&Vec<Point>>, from: i64, to: i64) -> Vec<Point> {
+    points
+        .map(|points| points.iter().filter(|p| from <= p.time && p.time <= to).cloned().collect())
+        .unwrap_or_default()
+}
+
+impl MetricSource for LocalSource {
+    fn services(&self) -> Result<Vec<Service>, String> {
+        Ok(self.services.clone())
+    }
+
+    fn hosts(&self, service: Option<&str>, role: Option<&str>) -> Result<Vec<Host>, String> {
+        Ok(self
+            .hosts
+            .iter()
+            .filter(|host| {
+                host.roles
+                    .iter()
+                    .any(|(s, r)| service.is_none_or(|service| s == service) && role.is_none_or(|role| r == role))
+                    || service.is_none() && role.is_none()
+            })
+            .cloned()
+            .collect())
+    }
+
+    fn host_metric_names(&self, host_id: &str) -> Result<Vec<String>, String> {
+        Ok(self.host_metrics.get(host_id).map(|m| m.keys().cloned().collect()).unwrap_or_default())
+    }
+
+    fn service_metric_names(&self, service_name: &str) -> Result<Vec<String>, String> {
+        Ok(self.service_metrics.get(service_name).map(|m| m.keys().cloned().collect()).unwrap_or_default())
+    }
+
+    fn host_metric_values(&self, host_id: &str, metric_name: &str, from: i64, to: i64) -> Result<Vec<Point>, String> {
+        Ok(points_between(self.host_metrics.get(host_id).and_then(|m| m.get(metric_name)), from, to))
+    }
+
+    fn service_metric_values(&self, service_name: &str, metric_name: &str, from: i64, to: i64) -> Result<Vec<Point>, String> {
+        Ok(points_between(self.service_metrics.get(service_name).and_then(|m| m.get(metric_name)), from, to))
+    }
+}
+
+pub fn fetch<S: MetricSource + ?Sized>(source: &S, metric: &Metric, from: i64, to: i64) -> Result<Vec<Series>, String> {
+    match *metric {
+        Metric::Host(ref host_id, ref metric_name) => {
+            let mut series = Vec::new();
+            for name in matching_names(source.host_metric_names(host_id)?, metric_name) {
+                let points = source.host_metric_values(host_id, &name, from, to)?;
+                series.push(Series { name, points });
+            }
+            Ok(series)
+        }
+        Metric::Service(ref service_name, ref metric_name) => {
+            let mut series = Vec::new();
+            for name in matching_names(source.service_metric_names(service_name)?, metric_name) {
+                let points = source.service_metric_values(service_name, &name, from, to)?;
+                series.push(Series { name, points });
+            }
+            Ok(series)
+        }
+        Metric::Role(ref service_name, ref role_name, ref metric_name)
+        | Metric::RoleSlot(ref service_name, ref role_name, ref metric_name) => {
+            let mut series = Vec::new();
+            for host in source.hosts(Some(service_name), Some(role_name))? {
+                for name in matching_names(source.host_metric_names(&host.id)?, metric_name) {
+                    let points = source.host_metric_values(&host.id, &name, from, to)?;
+                    series.push(Series { name: format!("{} {}", host.name, name), points });
+                }
+            }
+            Ok(series)
+        }
+        _ => Err(format!("not a metric reference: {:?}", metric)),
+    }
+}
+
+fn matching_names(names: Vec<String>, pattern: &str) -> Vec<String> {
+    names.into_iter().filter(|name| match_metric_name(pattern, name)).collect()
+}
+
+pub fn match_metric_name(pattern: &str, name: &str) -> bool {
+    let patterns = pattern.split('.').collect::<Vec<_>>();
+    let names = name.split('.').collect::<Vec<_>>();
+    patterns.len() == names.len() && patterns.iter().zip(names.iter()).all(|(p, n)| match_segment(p, n))
+}
+
+fn match_segment(pattern: &str, name: &str) -> bool {
+    match pattern.find('*') {
+        None => pattern == name,
+        Some(i) => {
+            let (prefix, rest) = (&pattern[..i], &pattern[i + 1..]);
+            name.starts_with(prefix)
+                && (prefix.len()..name.len() + 1)
+                    .filter(|&j| name.is_char_boundary(j))
+                    .any(|j| match_segment(rest, &name[j..]))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_metric_name() {
+        for &(pattern, name, expected) in &[
+            ("loadavg5", "loadavg5", true),
+            ("loadavg5", "loadavg15", false),
+            ("memory.*", "memory.used", true),
+            ("memory.*", "memory", false),
+            ("custom.foo.*.count", "custom.foo.bar.count", true),
+            ("custom.foo.*", "custom.foo.bar.count", false),
+            ("filesystem.*.used", "filesystem.drive.used", true),
+            ("cpu.*_percent", "cpu.user_percent", true),
+            ("cpu.*_percent", "cpu.user", false),
+        ] {
+            assert_eq!(match_metric_name(pattern, name), expected, "{} {}", pattern, name);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use functions;
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..b.len() + 1).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let next = *[row[j + 1] + 1, row[j] + 1, prev + if ca == cb { 0 } else { 1 }]
+                .iter()
+                .min()
+                .unwrap();
+            prev = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+pub fn suggest<S: AsRef<str>>(name: &str, candidates: &[S]) -> Vec<String> {
+    let lower = name.to_lowercase();
+    let threshold = ::std::cmp::max(1, name.chars().count() / 3);
+    let mut scored = candidates
+        .iter()
+        .map(|c| c.as_ref())
+        .filter(|&c| c != name)
+        .filter_map(|c| {
+            if c.to_lowercase() == lower {
+                Some((0, c))
+            } else {
+                let distance = edit_distance(&lower, &c.to_lowercase());
+                if distance <= threshold {
+                    Some((distance, c))
+                } else {
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    scored.sort();
+    scored.dedup();
+    scored.into_iter().take(3).map(|(_, c)| c.to_string()).collect()
+}
+
+pub fn suggest_abbreviation<S: AsRef<str>>(name: &str, candidates: &[S]) -> Vec<String> {
+    let lower = name.to_lowercase();
+    if lower.chars().count() < 3 {
+        return vec![];
+    }
+    candidates
+        .iter()
+        .map(|c| c.as_ref())
+        .filter(|&c| c != name && is_abbreviation(&lower, &c.to_lowercase()))
+        .take(3)
+        .map(|c| c.to_string())
+        .collect()
+}
+
+fn is_abbreviation(short: &str, long: &str) -> bool {
+    let mut long = long.chars();
+    short.chars().next() == long.clone().next() && short.chars().all(|c| long.any(|l| l == c))
+}
+
+pub(crate) const DURATION_UNITS: &[(&str, &[&str])] = &[
+    ("m", &["min", "mins", "minute", "minutes"]),
+    ("h", &["hr", "hrs", "hour", "hours"]),
+    ("d", &["day", "days"]),
+    ("w", &["wk", "wks", "week", "weeks"]),
+    ("mo", &["mon", "mons", "month", "months"]),
+    ("y", &["yr", "yrs", "year", "years"]),
+];
+
+pub fn suggest_duration(text: &str) -> Vec<String> {
+    let inner = text.trim_matches(['\'', '"']);
+    let unit_start = inner.find(|c: char| !c.is_ascii_digit()).unwrap_or(inner.len());
+    let (count, unit) = inner.split_at(unit_start);
+    if count.is_empty() || unit.len() < 2 {
+        return vec![];
+    }
+    let lower = unit.to_lowercase();
+    let units = match DURATION_UNITS.iter().find(|&&(_, names)| names.contains(&lower.as_str())) {
+        Some(&(unit, _)) => vec![unit.to_string()],
+        None => suggest(unit, &DURATION_UNITS.iter().map(|&(unit, _)| unit).collect::<Vec<_>>()),
+    };
+    units.into_iter().map(|unit| format!("{}{}", count, unit)).collect()
+}
+
+pub fn suggest_function(name: &str) -> Vec<String> {
+    let suggestions = suggest(name, &functions::names());
+    if suggestions.is_empty() {
+        suggest_abbreviation(name, &functions::names())
+    } else {
+        suggestions
+    }
+}
+
+pub fn did_you_mean(suggestions: Vec<String>) -> String {
+    if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!(" (did you mean {}?)", suggestions.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("db", ""), 2);
+        assert_eq!(edit_distance("db-slave", "db-slave"), 0);
+        assert_eq!(edit_distance("db-slave", "db-salve"), 2);
+        assert_eq!(edit_distance("22CXRB3pZmu", "22CXRB3pZmv"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn test_suggest() {
+        let candidates = vec!["db-master", "db-slave", "app", "DB"];
+        assert_eq!(suggest("db-salve", &candidates), vec!["db-slave"]);
+        assert_eq!(suggest("db", &candidates), vec!["DB"]);
+        assert_eq!(suggest("Ap", &candidates), vec!["app"]);
+        assert!(suggest("cache", &candidates).is_empty());
+    }
+
+    #[test]
+    fn test_suggest_abbreviation() {
+        let candidates = vec!["movingAverage", "max", "linearRegression"];
+        assert_eq!(suggest_abbreviation("movingAvg", &candidates), vec!["movingAverage"]);
+        assert_eq!(suggest_abbreviation("linReg", &candidates), vec!["linearRegression"]);
+        assert!(suggest_abbreviation("mx", &candidates).is_empty());
+        assert!(suggest_abbreviation("avg", &candidates).is_empty());
+    }
+}
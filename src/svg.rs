@@ -0,0 +1,249 @@
+use chrono::{Offset, TimeZone};
+use chrono_tz::Tz;
+
+use eval::Evaluated;
+use plot::{format_time, format_value, stack_bands, value_range};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SvgOptions {
+    pub width: usize,
+    pub height: usize,
+    pub from: i64,
+    pub to: i64,
+    pub timezone: Tz,
+}
+
+const COLORS: &[&str] = &[
+    "#2f7ed8", "#f28f43", "#8bbc21", "#910000", "#1aadce", "#492970", "#77a1e5", "#c42525", "#a6c96a",
+];
+
+const MARGIN_LEFT: f64 = 56.0;
+const MARGIN_RIGHT: f64 = 16.0;
+const MARGIN_TOP: f64 = 12.0;
+const AXIS_HEIGHT: f64 = 24.0;
+const LEGEND_ROW_HEIGHT: f64 = 18.0;
+
+const TIME_STEPS: &[i64] = &[
+    60, 300, 600, 1800, 3600, 3 * 3600, 6 * 3600, 12 * 3600, 86400, 2 * 86400, 7 * 86400, 14 * 86400, 30 * 86400,
+];
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn value_step(range: f64) -> f64 {
+    let raw = range / 5.0;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let normalized = raw / magnitude;
+    magnitude * if normalized <= 1.0 {
+        1.0
+    } else if normalized <= 2.0 {
+        2.0
+    } else if normalized <= 5.0 {
+        5.0
+    } else {
+        10.0
+    }
+}
+
+fn offset_seconds(timezone: &Tz, time: i64) -> i64 {
+    match timezone.timestamp_opt(time, 0).single() {
+        Some(datetime) => i64::from(datetime.offset().fix().local_minus_utc()),
+        None => 0,
+    }
+}
+
+fn time_step(span: i64) -> i64 {
+    *TIME_STEPS.iter().find(|&&step| span / step <= 8).unwrap_or(&TIME_STEPS[TIME_STEPS.len() - 1])
+}
+
+pub fn render_svg(evaluated: &[Evaluated], options: &SvgOptions) -> Result<String, String> {
+    let bands = stack_bands(evaluated);
+    let min_width = MARGIN_LEFT + MARGIN_RIGHT + 1.0;
+    let min_height = MARGIN_TOP + 1.0 + AXIS_HEIGHT + LEGEND_ROW_HEIGHT * bands.len() as f64;
+    if (options.width as f64) < min_width {
+        return Err(format!("width must be at least {}: {}", min_width, options.width));
+    }
+    if (options.height as f64) < min_height {
+        return Err(format!("height must be at least {} for {} series: {}", min_height, bands.len(), options.height));
+    }
+    let (min, max) = value_range(&bands).unwrap_or((0.0, 1.0));
+    let step = value_step(max - min);
+    let (min, max) = ((min / step).floor() * step, (max / step).ceil() * step);
+    let (width, height) = (options.width as f64, options.height as f64);
+    let legend_height = LEGEND_ROW_HEIGHT * bands.len() as f64;
+    let (left, right) = (MARGIN_LEFT, width - MARGIN_RIGHT);
+    let (top, bottom) = (MARGIN_TOP, height - AXIS_HEIGHT - legend_height);
+    let span = (options.to - options.from).max(1);
+    let x = |t: i64| left + (t - options.from) as f64 / span as f64 * (right - left);
+    let y = |v: f64| bottom - (v - min) / (max - min) * (bottom - top);
+
+    let mut svg = String::new();
+    svg.push_str(&format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"11\">\n",
+        w = options.width,
+        h = options.height
+    ));
+    svg.push_str(&format!("<rect width=\"{}\" height=\"{}\" fill=\"#ffffff\"/>\n", options.width, options.height));
+
+    svg.push_str("<g class=\"y-axis\">\n");
+    let mut value = min;
+    while value <= max + step / 2.0 {
+        svg.push_str(&format!(
+            "<line x1=\"{:.1}\" y1=\"{y:.1}\" x2=\"{:.1}\" y2=\"{y:.1}\" stroke=\"#e0e0e0\"/>\n",
+            left,
+            right,
+            y = y(value)
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\" fill=\"#666666\">{}</text>\n",
+            left - 6.0,
+            y(value) + 4.0,
+            format_value(value)
+        ));
+        value += step;
+    }
+    svg.push_str("</g>\n");
+
+    svg.push_str("<g class=\"x-axis\">\n");
+    svg.push_str(&format!(
+        "<line x1=\"{:.1}\" y1=\"{y:.1}\" x2=\"{:.1}\" y2=\"{y:.1}\" stroke=\"#999999\"/>\n",
+        left,
+        right,
+        y = bottom
+    ));
+    let step = time_step(span);
+    let offset = offset_seconds(&options.timezone, options.from);
+    let mut time = (options.from + offset + step - 1).div_euclid(step) * step - offset;
+    while time <= options.to {
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\" fill=\"#666666\">{}</text>\n",
+            x(time),
+            bottom + 16.0,
+            escape(&format_time(time, span, &options.timezone))
+        ));
+        time += step;
+    }
+    svg.push_str("</g>\n");
+
+    svg.push_str("<g class=\"series\">\n");
+    let mut stacked_paths = Vec::new();
+    let mut lines = Vec::new();
+    for (i, band) in bands.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        let upper = band
+            .upper
+            .iter()
+            .filter(|&&(_, v)| v.is_finite())
+            .map(|&(t, v)| format!("{:.1},{:.1}", x(t), y(v)))
+            .collect::<Vec<_>>();
+        if upper.is_empty() {
+            continue;
+        }
+        if band.stacked {
+            let lower = band
+                .lower
+                .iter()
+                .rev()
+                .filter(|&&(_, v)| v.is_finite())
+                .map(|&(t, v)| format!("{:.1},{:.1}", x(t), y(v)))
+                .collect::<Vec<_>>();
+            stacked_paths.push(format!(
+                "<path d=\"M{} L{} Z\" fill=\"{}\" fill-opacity=\"0.6\" stroke=\"none\"/>\n",
+                upper.join(" L"),
+                lower.join(" L"),
+                color
+            ));
+        }
+        lines.push(format!(
+            "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>\n",
+            upper.join(" "),
+            color
+        ));
+    }
+    for path in stacked_paths.iter().chain(lines.iter()) {
+        svg.push_str(path);
+    }
+    svg.push_str("</g>\n");
+
+    svg.push_str("<g class=\"legend\">\n");
+    for (i, band) in bands.iter().enumerate() {
+        let row = bottom + AXIS_HEIGHT + LEGEND_ROW_HEIGHT * i as f64;
+        svg.push_str(&format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"10\" height=\"10\" fill=\"{}\"/>\n",
+            left,
+            row,
+            COLORS[i % COLORS.len()]
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" fill=\"#333333\">{}</text>\n",
+            left + 16.0,
+            row + 9.0,
+            escape(&band.name)
+        ));
+    }
+    svg.push_str("</g>\n");
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use source::{Point, Series};
+
+    fn evaluated(name: &str, values: &[f64], stacked: bool) -> Evaluated {
+        Evaluated {
+            series: Series {
+                name: name.to_string(),
+                points: values.iter().enumerate().map(|(i, &value)| Point { time: i as i64 * 3600, value }).collect(),
+            },
+            stacked,
+        }
+    }
+
+    fn options(timezone: Tz) -> SvgOptions {
+        SvgOptions { width: 400, height: 200, from: 0, to: 3 * 3600, timezone }
+    }
+
+    #[test]
+    fn test_value_step() {
+        assert_eq!(value_step(10.0), 2.0);
+        assert_eq!(value_step(3.0), 1.0);
+        assert_eq!(value_step(0.4), 0.1);
+        assert_eq!(value_step(2000.0), 500.0);
+    }
+
+    #[test]
+    fn test_render_svg() {
+        let svg = render_svg(
+            &[evaluated("db <01>", &[1.0, 2.0, 3.0, 2.0], false), evaluated("db02", &[1.0, 1.0, 1.0, 1.0], true)],
+            &options(Tz::UTC),
+        )
+        .unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"400\" height=\"200\""));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(svg.contains(">db &lt;01&gt;</text>"));
+        assert!(svg.contains(">db02</text>"));
+        assert_eq!(svg.matches("<polyline ").count(), 2);
+        assert_eq!(svg.matches("<path ").count(), 1);
+        assert!(svg.contains(">00:00</text>"));
+        assert!(svg.contains(">03:00</text>"));
+        let svg = render_svg(&[evaluated("db01", &[1.0, 2.0], false)], &options(Tz::Asia__Tokyo)).unwrap();
+        assert!(svg.contains(">09:00</text>"));
+        assert!(svg.contains(">12:00</text>"));
+        let series = [evaluated("db01", &[1.0, 2.0], false)];
+        assert_eq!(
+            render_svg(&series, &SvgOptions { width: 0, ..options(Tz::UTC) }),
+            Err("width must be at least 73: 0".to_string())
+        );
+        assert_eq!(
+            render_svg(&series, &SvgOptions { height: 40, ..options(Tz::UTC) }),
+            Err("height must be at least 55 for 1 series: 40".to_string())
+        );
+    }
+}
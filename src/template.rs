@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+
+use pest::Parser;
+use pest::inputs::Input;
+use pest::iterators::Pair;
+
+use functions::{self, Function, Kind};
+use recover::{matches, parse_recovering};
+use {parse_metric_spanned, Metric, Span};
+
+#[derive(Parser)]
+#[grammar = "template.pest"]
+pub struct TemplateParser;
+
+pub type Bindings = BTreeMap<String, Vec<String>>;
+
+pub fn bindings_from_csv(src: &str) -> Result<Bindings, String> {
+    let mut rows = src.lines().filter(|line| !line.trim().is_empty()).map(split_csv_line);
+    let header = rows.next().ok_or_else(|| "empty csv".to_string())??;
+    let mut bindings = header.iter().map(|name| (name.clone(), Vec::new())).collect::<Bindings>();
+    for (i, row) in rows.enumerate() {
+        let row = row?;
+        if row.len() != header.len() {
+            return Err(format!("csv line {}: expected {} fields but got {}", i + 2, header.len(), row.len()));
+        }
+        for (name, value) in header.iter().zip(row) {
+            bindings.get_mut(name).unwrap().push(value);
+        }
+    }
+    Ok(bindings)
+}
+
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => fields.push(::std::mem::take(&mut field).trim().to_string()),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("unterminated quote in csv: {}", line));
+    }
+    fields.push(field.trim().to_string());
+    Ok(fields)
+}
+
+struct Output<'a> {
+    template: &'a str,
+    text: String,
+    origins: Vec<(usize, usize, bool)>,
+    calls: Vec<(Option<&'static Function>, usize)>,
+}
+
+impl<'a> Output<'a> {
+    fn push(&mut self, s: &str, origin: usize, exact: bool) {
+        self.origins.push((self.text.len(), origin, exact));
+        self.text.push_str(s);
+    }
+
+    fn origin(&self, offset: usize) -> usize {
+        match self.origins.iter().rev().find(|&&(start, _, _)| start <= offset) {
+            Some(&(start, origin, true)) => origin + (offset - start),
+            Some(&(_, origin, false)) => origin,
+            None => 0,
+        }
+    }
+
+    fn error(&self, origin: usize, message: String) -> String {
+        let (line, col) = Span { start: origin, end: origin }.line_col(self.template);
+        format!("{}:{}: {}", line, col, message)
+    }
+
+    fn next_argument(&mut self) {
+        if let Some(call) = self.calls.last_mut() {
+            call.1 += 1;
+        }
+    }
+
+    fn kind(&self) -> Option<Kind> {
+        self.calls.last().and_then(|&(function, i)| function.and_then(|function| function.kind(i)))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Context {
+    Whole,
+    Partial,
+    Quoted(char),
+}
+
+fn span_start<I: Input>(pair: &Pair<Rule, I>) -> usize {
+    pair.clone().into_span().start()
+}
+
+fn expand_output<'a>(template: &'a str, bindings: &Bindings) -> Result<Output<'a>, String> {
+    let mut pairs = TemplateParser::parse_str(Rule::whole_template, template).map_err(|e| format!("{}", e))?;
+    let mut output = Output { template, text: String::new(), origins: Vec::new(), calls: Vec::new() };
+    for pair in pairs.next().into_iter().flat_map(|pair| pair.into_inner()) {
+        expand_pair(pair, bindings, &mut output, Context::Partial)?;
+    }
+    Ok(output)
+}
+
+pub fn expand(template: &str, bindings: &Bindings) -> Result<String, String> {
+    expand_output(template, bindings).map(|output| output.text)
+}
+
+pub fn render(template: &str, bindings: &Bindings) -> Result<Metric, String> {
+    let output = expand_output(template, bindings)?;
+    parse_metric_spanned(&output.text).map_err(|err| output.error(output.origin(err.span.start), err.summary().to_string()))
+}
+
+pub fn unknown_functions(template: &str, bindings: &Bindings) -> Result<Vec<(Span, String)>, String> {
+    let output = expand_output(template, bindings)?;
+    Ok(parse_recovering(&output.text)
+        .warnings
+        .into_iter()
+        .map(|warning| {
+            let origin = output.origin(warning.span.start);
+            (Span { start: origin, end: origin }, warning.message)
+        })
+        .collect())
+}
+
+fn expand_children<I: Input>(pair: Pair<Rule, I>, bindings: &Bindings, output: &mut Output) -> Result<(), String> {
+    let children = pair.into_inner().collect::<Vec<_>>();
+    let mut contexts = vec![Context::Partial; children.len()];
+    let commas = (0..children.len()).filter(|&i| children[i].as_rule() == Rule::comma);
+    let mut start = 0;
+    for end in commas.chain(Some(children.len())) {
+        let group = &children[start..end];
+        let placeholders = (0..group.len()).filter(|&i| group[i].as_rule() == Rule::placeholder).collect::<Vec<_>>();
+        let blank = group.iter().all(|child| {
+            child.as_rule() == Rule::placeholder || (child.as_rule() == Rule::text && child.as_str().trim().is_empty())
+        });
+        if let ([i], true) = (placeholders.as_slice(), blank) {
+            contexts[start + i] = Context::Whole;
+        }
+        start = end + 1;
+    }
+    for (child, context) in children.into_iter().zip(contexts) {
+        expand_pair(child, bindings, output, context)?;
+    }
+    Ok(())
+}
+
+fn check_value(output: &Output, origin: usize, name: &str, value: &str, context: Context) -> Result<(), String> {
+    let valid = match (context, output.kind()) {
+        (Context::Whole, Some(kind)) => kind.rule().is_none_or(|rule| matches(rule, value.trim())),
+        (Context::Quoted(quote), _) => !value.contains(quote),
+        _ => !value.contains(['(', ')', ',', '\'', '"']),
+    };
+    if valid {
+        Ok(())
+    } else {
+        let message = match (context, output.kind()) {
+            (Context::Whole, Some(kind)) => kind.invalid(value),
+            _ => format!("invalid value: {}", value),
+        };
+        Err(output.error(origin, format!("${}: {}", name, message)))
+    }
+}
+
+fn expand_pair<I: Input>(pair: Pair<Rule, I>, bindings: &Bindings, output: &mut Output, context: Context) -> Result<(), String> {
+    let origin = span_start(&pair);
+    match pair.as_rule() {
+        Rule::items | Rule::body => expand_children(pair, bindings, output)?,
+        Rule::nested => {
+            let name_start = output.text.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_').len();
+            let function = functions::function(&output.text[name_start..]);
+            let end = origin + pair.as_str().len() - 1;
+            output.push("(", origin, true);
+            output.calls.push((function, 0));
+            for pair in pair.into_inner() {
+                expand_pair(pair, bindings, output, Context::Partial)?;
+            }
+            output.calls.pop();
+            output.push(")", end, true);
+        }
+        Rule::quoted => {
+            let quote = if pair.as_str().starts_with('\'') { '\'' } else { '"' };
+            let end = origin + pair.as_str().len() - 1;
+            output.push(&quote.to_string(), origin, true);
+            for pair in pair.into_inner() {
+                expand_pair(pair, bindings, output, Context::Quoted(quote))?;
+            }
+            output.push(&quote.to_string(), end, true);
+        }
+        Rule::comma => {
+            output.next_argument();
+            output.push(pair.as_str(), origin, true);
+        }
+        Rule::placeholder => {
+            let name = pair.into_inner().next().map(|p| p.as_str().to_string()).unwrap_or_default();
+            match bindings.get(&name).map(|values| values.as_slice()) {
+                Some([value]) => {
+                    check_value(output, origin, &name, value, context)?;
+                    output.push(value, origin, false);
+                }
+                Some(values) => {
+                    let message = format!("${} has {} values; iterate over it with for", name, values.len());
+                    return Err(output.error(origin, message));
+                }
+                None => return Err(output.error(origin, format!("unbound variable: ${}", name))),
+            }
+        }
+        Rule::for_loop => {
+            let mut inner = pair.into_inner();
+            let (variable, list, body) = match (inner.next(), inner.next(), inner.next()) {
+                (Some(variable), Some(list), Some(body)) => (variable, list, body),
+                _ => return Err(output.error(origin, "invalid for loop".to_string())),
+            };
+            let list_origin = span_start(&list);
+            let variable = variable.into_inner().next().map(|p| p.as_str().to_string()).unwrap_or_default();
+            let list = list.into_inner().next().map(|p| p.as_str().to_string()).unwrap_or_default();
+            let values = match bindings.get(&list) {
+                Some(values) if values.is_empty() => return Err(output.error(list_origin, format!("no values for ${}", list))),
+                Some(values) => values,
+                None => return Err(output.error(list_origin, format!("unbound variable: ${}", list))),
+            };
+            let mut bindings = bindings.clone();
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    output.next_argument();
+                    output.push(", ", origin, false);
+                }
+                bindings.insert(variable.clone(), vec![value.clone()]);
+                expand_pair(body.clone(), &bindings, output, Context::Partial)?;
+            }
+        }
+        _ => output.push(pair.as_str(), origin, true),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compact_print;
+
+    fn bindings(pairs: &[(&str, &[&str])]) -> Bindings {
+        pairs
+            .iter()
+            .map(|&(name, values)| (name.to_string(), values.iter().map(|v| v.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn test_bindings_from_csv() {
+        assert_eq!(
+            bindings_from_csv("host,name\n22CXRB3pZmu, db01\n3NpZbVH2B6f,\"db \"\"02\"\"\"\n").unwrap(),
+            bindings(&[("host", &["22CXRB3pZmu", "3NpZbVH2B6f"]), ("name", &["db01", "db \"02\""])])
+        );
+        assert!(bindings_from_csv("host,name\n22CXRB3pZmu\n").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let vars = bindings(&[
+            ("host", &["22CXRB3pZmu"]),
+            ("service", &["Blog"]),
+            ("hosts", &["22CXRB3pZmu", "3NpZbVH2B6f"]),
+        ]);
+        assert_eq!(
+            compact_print(render("alias(host($host, loadavg5), '${service} $host')", &vars).unwrap()),
+            "alias(host(22CXRB3pZmu, loadavg5), 'Blog 22CXRB3pZmu')"
+        );
+        assert_eq!(
+            compact_print(
+                render(
+                    "group(service(${service}, count), for $h in $hosts: alias(host($h, loadavg5), \"load $h\"))",
+                    &vars
+                ).unwrap()
+            ),
+            "group(service(Blog, count), alias(host(22CXRB3pZmu, loadavg5), 'load 22CXRB3pZmu'), alias(host(3NpZbVH2B6f, loadavg5), 'load 3NpZbVH2B6f'))"
+        );
+        assert_eq!(render("host($unknown, loadavg5)", &vars), Err("1:6: unbound variable: $unknown".to_string()));
+        assert_eq!(
+            render("host($hosts, loadavg5)", &vars),
+            Err("1:6: $hosts has 2 values; iterate over it with for".to_string())
+        );
+        assert!(render("host($host loadavg5)", &vars).is_err());
+    }
+
+    #[test]
+    fn test_unknown_functions() {
+        let vars = bindings(&[("host", &["22CXRB3pZmu"]), ("f", &["newFunction"])]);
+        assert!(unknown_functions("host($host, loadavg5)", &vars).unwrap().is_empty());
+        let warnings = unknown_functions("group(\n  $f(host($host, loadavg5)),\n  newFunction(host($host, cpu)))", &vars);
+        assert_eq!(
+            warnings.unwrap().into_iter().map(|(span, message)| (span.start, message)).collect::<Vec<_>>(),
+            vec![(9, "unknown function: newFunction".to_string()), (38, "unknown function: newFunction".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_render_checks_values() {
+        let vars = bindings(&[("h", &["a), host(b"]), ("d", &["1s"]), ("n", &["it's"]), ("m", &["disk"])]);
+        assert_eq!(
+            render("avg(\n  host($h, loadavg5))", &vars),
+            Err("2:8: $h: invalid host id: a), host(b".to_string())
+        );
+        assert_eq!(
+            render("timeShift(host(a, b), $d)", &vars),
+            Err("1:23: $d: invalid duration: 1s".to_string())
+        );
+        assert_eq!(render("alias(host(a, b), 'x $n')", &vars), Err("1:22: $n: invalid value: it's".to_string()));
+        assert_eq!(
+            compact_print(render("alias(host(a, custom.$m.used), \"$n\")", &vars).unwrap()),
+            "alias(host(a, custom.disk.used), \"it's\")"
+        );
+        assert_eq!(render("avg(host($m, b)) x", &vars).map_err(|e| e[..5].to_string()), Err("1:14:".to_string()));
+    }
+}
@@ -0,0 +1,145 @@
+use functions;
+use {pretty_print_argument, Argument, Metric};
+
+struct Node {
+    function: String,
+    arguments: Vec<(&'static str, String)>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(metric: &Metric) -> Node {
+        let function = functions::function(metric.function_name());
+        let (name, args) = metric.clone().into_call();
+        let mut arguments = vec![];
+        let mut children = vec![];
+        for (i, arg) in args.into_iter().enumerate() {
+            let parameter = function.and_then(|function| function.parameters.get(i));
+            let label = match (parameter, &arg) {
+                (Some(parameter), _) => parameter.name,
+                (None, &Argument::Factor(_)) => "factor",
+                (None, &Argument::Duration(_)) => "duration",
+                (None, _) => "string",
+            };
+            match arg {
+                Argument::Metric(ref metric) => children.push(Node::new(metric)),
+                arg => arguments.push((label, pretty_print_argument(arg, parameter.map(|parameter| parameter.kind)))),
+            }
+        }
+        Node { function: name, arguments, children }
+    }
+
+    fn label(&self, separator: &str) -> String {
+        let mut label = self.function.clone();
+        for &(name, ref value) in &self.arguments {
+            label.push_str(&format!("{}{}: {}", separator, name, value));
+        }
+        label
+    }
+
+    fn walk<F: FnMut(usize, &Node, Option<usize>)>(&self, id: &mut usize, parent: Option<usize>, f: &mut F) {
+        let current = *id;
+        *id += 1;
+        f(current, self, parent);
+        for child in &self.children {
+            child.walk(id, Some(current), f);
+        }
+    }
+}
+
+pub fn to_ascii(metric: &Metric) -> String {
+    fn write(node: &Node, prefix: &str, last: bool, root: bool, output: &mut String) {
+        let (branch, indent) = match (root, last) {
+            (true, _) => ("", ""),
+            (false, true) => ("`-- ", "    "),
+            (false, false) => ("|-- ", "|   "),
+        };
+        let arguments = node.arguments.iter().map(|&(name, ref value)| format!("{}: {}", name, value));
+        let arguments = arguments.collect::<Vec<_>>().join(", ");
+        if arguments.is_empty() {
+            output.push_str(&format!("{}{}{}\n", prefix, branch, node.function));
+        } else {
+            output.push_str(&format!("{}{}{} ({})\n", prefix, branch, node.function, arguments));
+        }
+        let prefix = format!("{}{}", prefix, indent);
+        for (i, child) in node.children.iter().enumerate() {
+            write(child, &prefix, i + 1 == node.children.len(), false, output);
+        }
+    }
+    let mut output = String::new();
+    write(&Node::new(metric), "", true, true, &mut output);
+    output
+}
+
+pub fn to_dot(metric: &Metric) -> String {
+    let mut output = "digraph metric {\n  node [shape=box];\n".to_string();
+    Node::new(metric).walk(&mut 0, None, &mut |id, node, parent| {
+        let label = node.label("\n").replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+        let shape = if node.children.is_empty() { ", shape=ellipse" } else { "" };
+        output.push_str(&format!("  n{} [label=\"{}\"{}];\n", id, label, shape));
+        if let Some(parent) = parent {
+            output.push_str(&format!("  n{} -> n{};\n", parent, id));
+        }
+    });
+    output.push_str("}\n");
+    output
+}
+
+pub fn to_mermaid(metric: &Metric) -> String {
+    let mut output = "graph TD\n".to_string();
+    Node::new(metric).walk(&mut 0, None, &mut |id, node, parent| {
+        let label = node.label("<br>").replace('"', "#quot;");
+        if node.children.is_empty() {
+            output.push_str(&format!("  n{}([\"{}\"])\n", id, label));
+        } else {
+            output.push_str(&format!("  n{}[\"{}\"]\n", id, label));
+        }
+        if let Some(parent) = parent {
+            output.push_str(&format!("  n{} --> n{}\n", parent, id));
+        }
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_metric;
+
+    const SRC: &str =
+        "alias(scale(diff(host(22CXRB3pZmu, loadavg5), timeShift(avg(role(Blog:db, loadavg5)), 1w)), 1/2), 'load \"diff\"')";
+
+    #[test]
+    fn test_to_ascii() {
+        assert_eq!(
+            to_ascii(&parse_metric(SRC).unwrap()),
+            r#"alias (name: 'load "diff"')
+`-- scale (factor: 1/2)
+    `-- diff
+        |-- host (host: 22CXRB3pZmu, metric: loadavg5)
+        `-- timeShift (duration: 1w)
+            `-- avg
+                `-- role (role: Blog:db, metric: loadavg5)
+"#
+        );
+        assert_eq!(
+            to_ascii(&parse_metric("newFunction(host(22CXRB3pZmu, loadavg5), 1h, 'a b')").unwrap()),
+            "newFunction (duration: 1h, string: 'a b')\n`-- host (host: 22CXRB3pZmu, metric: loadavg5)\n"
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let dot = to_dot(&parse_metric(SRC).unwrap());
+        assert!(dot.starts_with("digraph metric {\n  node [shape=box];\n  n0 [label=\"alias\\nname: 'load \\\"diff\\\"'\"];\n"));
+        assert!(dot.contains("  n3 [label=\"host\\nhost: 22CXRB3pZmu\\nmetric: loadavg5\", shape=ellipse];\n  n2 -> n3;\n"));
+        assert!(dot.ends_with("  n5 -> n6;\n}\n"));
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let mermaid = to_mermaid(&parse_metric(SRC).unwrap());
+        assert!(mermaid.starts_with("graph TD\n  n0[\"alias<br>name: 'load #quot;diff#quot;'\"]\n  n1[\"scale<br>factor: 1/2\"]\n"));
+        assert!(mermaid.contains("  n6([\"role<br>role: Blog:db<br>metric: loadavg5\"])\n  n5 --> n6\n"));
+    }
+}
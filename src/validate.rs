@@ -0,0 +1,293 @@
+use pest::inputs::Input;
+use pest::iterators::{Pair, Pairs};
+use serde_json::{self, Value};
+use serde_yaml;
+
+use dialect::{self, Dialect};
+use functions::{self, Kind};
+use source::{hosts_from_json, services_from_json, Host, MetricSource, Service};
+use suggest::suggest;
+use {parse_metric_pairs, Argument, Metric, Rule, Span};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Inventory {
+    pub services: Vec<Service>,
+    pub hosts: Vec<Host>,
+}
+
+impl Inventory {
+    pub fn from_json(src: &str) -> Result<Inventory, String> {
+        Inventory::from_value(&serde_json::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_yaml(src: &str) -> Result<Inventory, String> {
+        Inventory::from_value(&serde_yaml::from_str(src).map_err(|e| format!("{}", e))?)
+    }
+
+    pub fn from_value(value: &Value) -> Result<Inventory, String> {
+        Ok(Inventory {
+            services: if value.get("services").is_some() { services_from_json(value)? } else { Vec::new() },
+            hosts: if value.get("hosts").is_some() { hosts_from_json(value)? } else { Vec::new() },
+        })
+    }
+
+    pub fn from_source<S: MetricSource + ?Sized>(source: &S) -> Result<Inventory, String> {
+        Ok(Inventory { services: source.services()?, hosts: source.hosts(None, None)? })
+    }
+
+    fn service_names(&self) -> Vec<String> {
+        let mut names = self.services.iter().map(|s| s.name.clone()).collect::<Vec<_>>();
+        for host in &self.hosts {
+            names.extend(host.roles.iter().map(|(s, _)| s.clone()));
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    fn role_names(&self, service_name: &str) -> Vec<String> {
+        let mut names = Vec::new();
+        for service in self.services.iter().filter(|s| s.name == service_name) {
+            names.extend(service.roles.iter().cloned());
+        }
+        for host in &self.hosts {
+            names.extend(host.roles.iter().filter(|(s, _)| s == service_name).map(|(_, r)| r.clone()));
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Reference {
+    Host(String, Span),
+    Service(String, Span),
+    Role(String, Span, String, Span),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Problem {
+    pub span: Span,
+    pub message: String,
+    pub suggestions: Vec<String>,
+}
+
+pub fn collect_references(src: &str) -> Result<Vec<Reference>, String> {
+    let (metric, pairs) = parse_metric_pairs(src)?;
+    Ok(metric_references(&metric, pairs))
+}
+
+fn metric_references<I: Input>(metric: &Metric, pairs: Pairs<Rule, I>) -> Vec<Reference> {
+    let mut names = Vec::new();
+    collect_names(metric, &mut names);
+    let mut literals = Vec::new();
+    for pair in pairs {
+        collect_literals(pair, &mut literals);
+    }
+    names
+        .into_iter()
+        .zip(literals)
+        .filter_map(|((kind, name), (text, span))| match kind {
+            Kind::HostId => Some(Reference::Host(name, span)),
+            Kind::ServiceName => Some(Reference::Service(name, span)),
+            _ => {
+                let (service_name, role_name) = name.split_at(name.find(':')?);
+                let i = text.find(':')?;
+                let role_start = i + 1 + text[i + 1..].len() - text[i + 1..].trim_start().len();
+                Some(Reference::Role(
+                    service_name.to_string(),
+                    Span { start: span.start, end: span.start + i },
+                    role_name[1..].to_string(),
+                    Span { start: span.start + role_start, end: span.end },
+                ))
+            }
+        })
+        .collect()
+}
+
+fn is_reference(kind: Option<Kind>) -> bool {
+    kind == Some(Kind::HostId) || kind == Some(Kind::ServiceName) || kind == Some(Kind::RoleFullName)
+}
+
+fn collect_names(metric: &Metric, names: &mut Vec<(Kind, String)>) {
+    let function = functions::function(metric.function_name());
+    for (i, arg) in metric.clone().into_call().1.into_iter().enumerate() {
+        let kind = function.and_then(|function| function.kind(i));
+        match arg {
+            Argument::Metric(ref metric) => collect_names(metric, names),
+            Argument::String(name) if is_reference(kind) => names.push((kind.unwrap(), name)),
+            _ => {}
+        }
+    }
+}
+
+fn span_of<I: Input>(pair: &Pair<Rule, I>) -> Span {
+    let span = pair.clone().into_span();
+    Span { start: span.start(), end: span.end() }
+}
+
+fn literal_inner<I: Input>(pair: Pair<Rule, I>) -> (String, Span) {
+    let text = pair.as_str();
+    let span = span_of(&pair);
+    if text.len() >= 2 && (text.starts_with('\'') || text.starts_with('"')) {
+        (text[1..text.len() - 1].to_string(), Span { start: span.start + 1, end: span.end - 1 })
+    } else {
+        (text.to_string(), span)
+    }
+}
+
+fn collect_literals<I: Input>(pair: Pair<Rule, I>, literals: &mut Vec<(String, Span)>) {
+    if pair.as_rule() != Rule::metrics {
+        for inner in pair.into_inner() {
+            collect_literals(inner, literals);
+        }
+        return;
+    }
+    let mut inner = pair.into_inner();
+    let function = inner.next().and_then(|name| functions::function(name.as_str()));
+    for (i, arg) in inner.enumerate() {
+        let value = match arg.into_inner().next() {
+            Some(value) => value,
+            None => continue,
+        };
+        if is_reference(function.and_then(|function| function.kind(i))) {
+            literals.push(literal_inner(value));
+        } else {
+            collect_literals(value, literals);
+        }
+    }
+}
+
+pub fn validate(src: &str, inventory: &Inventory) -> Result<Vec<Problem>, String> {
+    validate_with_dialect(src, inventory, &Dialect::default())
+}
+
+pub fn validate_with_dialect(src: &str, inventory: &Inventory, dialect: &Dialect) -> Result<Vec<Problem>, String> {
+    let (metric, pairs) = parse_metric_pairs(src)?;
+    let host_ids = inventory.hosts.iter().map(|h| h.id.clone()).collect::<Vec<_>>();
+    let service_names = inventory.service_names();
+    let mut problems = dialect::check_pairs(pairs.clone(), dialect)
+        .into_iter()
+        .map(|err| Problem { span: err.span, message: err.message, suggestions: Vec::new() })
+        .collect::<Vec<_>>();
+    for reference in metric_references(&metric, pairs) {
+        match reference {
+            Reference::Host(host_id, span) => if !host_ids.contains(&host_id) {
+                problems.push(Problem {
+                    span,
+                    message: format!("unknown host: {}", host_id),
+                    suggestions: suggest(&host_id, &host_ids),
+                });
+            },
+            Reference::Service(service_name, span) => if !service_names.contains(&service_name) {
+                problems.push(Problem {
+                    span,
+                    message: format!("unknown service: {}", service_name),
+                    suggestions: suggest(&service_name, &service_names),
+                });
+            },
+            Reference::Role(service_name, service_span, role_name, role_span) => {
+                if !service_names.contains(&service_name) {
+                    problems.push(Problem {
+                        span: service_span,
+                        message: format!("unknown service: {}", service_name),
+                        suggestions: suggest(&service_name, &service_names),
+                    });
+                    continue;
+                }
+                let role_names = inventory.role_names(&service_name);
+                if !role_names.contains(&role_name) {
+                    problems.push(Problem {
+                        span: role_span,
+                        message: format!("unknown role: {}:{}", service_name, role_name),
+                        suggestions: suggest(&role_name, &role_names)
+                            .into_iter()
+                            .map(|r| format!("{}:{}", service_name, r))
+                            .collect(),
+                    });
+                }
+            }
+        }
+    }
+    problems.sort_by_key(|problem| problem.span.start);
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inventory() -> Inventory {
+        Inventory::from_json(
+            r#"{
+              "services": [{ "name": "Blog", "roles": ["db-master", "db-slave", "app"] }],
+              "hosts": [
+                { "id": "22CXRB3pZmu", "name": "db01", "roles": { "Blog": ["db-master"] } },
+                { "id": "22CXRB3pZmv", "name": "app01", "roleFullnames": ["Blog:app", "Shop:app"] }
+              ]
+            }"#,
+        ).unwrap()
+    }
+
+    #[test]
+    fn test_inventory() {
+        let inventory = inventory();
+        assert_eq!(inventory.service_names(), vec!["Blog", "Shop"]);
+        assert_eq!(inventory.role_names("Shop"), vec!["app"]);
+        let yaml = Inventory::from_yaml(
+            "services:\n  - name: Blog\n    roles: [db-master, db-slave, app]\nhosts:\n  - id: 22CXRB3pZmu\n    name: db01\n    roles:\n      Blog: [db-master]\n  - id: 22CXRB3pZmv\n    name: app01\n    roleFullnames: ['Blog:app', 'Shop:app']\n",
+        ).unwrap();
+        assert_eq!(yaml, inventory);
+    }
+
+    #[test]
+    fn test_validate() {
+        let src = "group(host(22CXRB3pZmx, loadavg5), service(blog, foo.bar), role('Blog:db-salve', loadavg5), roleSlots(Shop:app, loadavg5))";
+        assert_eq!(
+            validate(src, &inventory()),
+            Ok(vec![
+                Problem {
+                    span: Span { start: 11, end: 22 },
+                    message: "unknown host: 22CXRB3pZmx".to_string(),
+                    suggestions: vec!["22CXRB3pZmu".to_string(), "22CXRB3pZmv".to_string()],
+                },
+                Problem {
+                    span: Span { start: 43, end: 47 },
+                    message: "unknown service: blog".to_string(),
+                    suggestions: vec!["Blog".to_string()],
+                },
+                Problem {
+                    span: Span { start: 70, end: 78 },
+                    message: "unknown role: Blog:db-salve".to_string(),
+                    suggestions: vec!["Blog:db-slave".to_string()],
+                },
+            ])
+        );
+        assert_eq!(validate("host(22CXRB3pZmu, loadavg5)", &inventory()), Ok(vec![]));
+        assert!(validate("host(22CXRB3pZmu)", &inventory()).is_err());
+        assert_eq!(
+            collect_references("newFunction(role(Blog: db, x), 1h, host(22CXRB3pZmx, x))"),
+            Ok(vec![
+                Reference::Role(
+                    "Blog".to_string(),
+                    Span { start: 17, end: 21 },
+                    "db".to_string(),
+                    Span { start: 23, end: 25 },
+                ),
+                Reference::Host("22CXRB3pZmx".to_string(), Span { start: 40, end: 51 }),
+            ])
+        );
+        let dialect = Dialect::from_json(r#"{"name": "legacy", "quoted_literals": false}"#).unwrap();
+        assert_eq!(
+            validate_with_dialect("host('22CXRB3pZmx', loadavg5)", &inventory(), &dialect).map(|problems| problems
+                .into_iter()
+                .map(|problem| problem.message)
+                .collect::<Vec<_>>()),
+            Ok(vec![
+                "quoted host id is not available in dialect legacy".to_string(),
+                "unknown host: 22CXRB3pZmx".to_string(),
+            ])
+        );
+    }
+}
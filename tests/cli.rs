@@ -0,0 +1,47 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn mmpp(args: &[&str], stdin: &str) -> (bool, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mmpp"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.success(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn test_expand_unknown_functions() {
+    let library = env::temp_dir().join(format!("mmpp-cli-{}.mmpp", std::process::id()));
+    fs::write(&library, "errorRate(svc, role) = divide(sum(role($svc:$role, errors)), sum(role($svc:$role, requests)))\n")
+        .unwrap();
+    let library = library.to_str().unwrap();
+    let src = "alias(errRate(Blog, app), x)\n";
+    assert_eq!(
+        mmpp(&["expand", "--library", library], src),
+        (
+            true,
+            "alias(\n  errRate(Blog, app),\n  x\n)\n".to_string(),
+            "1:7: warning: unknown function or macro: errRate (did you mean errorRate?)\n".to_string()
+        )
+    );
+    assert_eq!(
+        mmpp(&["expand", "--library", library, "--unknown-functions=allow"], src),
+        (true, "alias(\n  errRate(Blog, app),\n  x\n)\n".to_string(), String::new())
+    );
+    assert_eq!(
+        mmpp(&["expand", "--library", library, "--unknown-functions=error"], src),
+        (false, "1:7: unknown function or macro: errRate (did you mean errorRate?)\n".to_string(), String::new())
+    );
+    fs::remove_file(library).unwrap();
+}
@@ -0,0 +1,65 @@
+extern crate mmpp;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const BLESS: &str = "MMPP_BLESS";
+
+fn run(src: &str) -> (&'static str, String) {
+    match mmpp::parse_metric_spanned(src) {
+        Ok(metric) => ("out", mmpp::pretty_print(metric) + "\n"),
+        Err(err) => {
+            let (line, col) = err.span.line_col(src);
+            ("err", format!("{}:{}: {}\n", line, col, err.summary()))
+        }
+    }
+}
+
+fn inputs(dir: &Path) -> Vec<PathBuf> {
+    let mut paths = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("in".as_ref()))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn test_corpus() {
+    let bless = env::var_os(BLESS).is_some();
+    let paths = inputs(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("corpus"));
+    assert!(!paths.is_empty());
+    let mut failures = Vec::new();
+    for path in paths {
+        let src = fs::read_to_string(&path).unwrap();
+        let (kind, actual) = run(&src);
+        let expected_path = path.with_extension(kind);
+        let stale_path = path.with_extension(if kind == "out" { "err" } else { "out" });
+        if bless {
+            fs::write(&expected_path, &actual).unwrap();
+            if stale_path.exists() {
+                fs::remove_file(&stale_path).unwrap();
+            }
+            continue;
+        }
+        match fs::read_to_string(&expected_path) {
+            Ok(ref expected) if *expected == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{}\n--- expected\n{}--- actual\n{}",
+                expected_path.display(),
+                expected,
+                actual
+            )),
+            Err(_) => failures.push(format!("{}: missing\n--- actual\n{}", expected_path.display(), actual)),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{}\n{} snapshots differ; set {}=1 to update them",
+        failures.join("\n"),
+        failures.len(),
+        BLESS
+    );
+}
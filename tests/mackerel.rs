@@ -0,0 +1,140 @@
+#![cfg(feature = "mackerel")]
+
+extern crate mmpp;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use mmpp::mackerel::Client;
+use mmpp::source::{fetch, Host, MetricSource, Point, Series, Service};
+use mmpp::Metric;
+
+const API_KEY: &str = "test-api-key";
+
+fn respond(path: &str) -> (u16, &'static str) {
+    match path {
+        "/api/v0/services" => (200, r#"{"services":[{"name":"Blog","memo":"","roles":["db","app"]}]}"#),
+        "/api/v0/hosts?service=Blog&role=db" => (
+            200,
+            r#"{"hosts":[{"id":"22CXRB3pZmu","name":"db01","roles":{"Blog":["db"]}},{"id":"22CXRB3pZmv","name":"db02","roles":{"Blog":["db","app"]}}]}"#,
+        ),
+        "/api/v0/hosts/22CXRB3pZmu/metric-names" | "/api/v0/hosts/22CXRB3pZmv/metric-names" => {
+            (200, r#"{"names":["loadavg5","memory.used","memory.free"]}"#)
+        }
+        "/api/v0/services/My%20Blog%2Fv2/metric-names" => (200, r#"{"names":["latency"]}"#),
+        "/api/v0/services/Blog/metric-names" => (200, r#"{"names":["access_count.2xx","access_count.5xx","latency"]}"#),
+        "/api/v0/hosts/22CXRB3pZmu/metrics?name=loadavg5&from=100&to=200" => {
+            (200, r#"{"metrics":[{"time":120,"value":1.5},{"time":180,"value":2}]}"#)
+        }
+        "/api/v0/hosts/22CXRB3pZmv/metrics?name=loadavg5&from=100&to=200" => {
+            (200, r#"{"metrics":[{"time":120,"value":0.5}]}"#)
+        }
+        "/api/v0/services/Blog/metrics?name=access_count.2xx&from=100&to=200" => {
+            (200, r#"{"metrics":[{"time":120,"value":10}]}"#)
+        }
+        "/api/v0/services/Blog/metrics?name=access_count.5xx&from=100&to=200" => {
+            (200, r#"{"metrics":[{"time":120,"value":1}]}"#)
+        }
+        _ => (404, r#"{"error":{"message":"not found"}}"#),
+    }
+}
+
+fn serve() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut authorized = false;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if line.to_lowercase().starts_with("x-api-key:") && line[10..].trim() == API_KEY {
+                    authorized = true;
+                }
+            }
+            let path = request_line.split(' ').nth(1).unwrap_or("");
+            let (status, body) = if authorized { respond(path) } else { (403, r#"{"error":"forbidden"}"#) };
+            write!(
+                stream,
+                "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            ).unwrap();
+        }
+    });
+    format!("http://{}", addr)
+}
+
+#[test]
+fn test_services() {
+    let client = Client::with_base_url(&serve(), API_KEY);
+    assert_eq!(
+        client.services(),
+        Ok(vec![Service { name: "Blog".to_string(), roles: vec!["db".to_string(), "app".to_string()] }])
+    );
+}
+
+#[test]
+fn test_hosts() {
+    let client = Client::with_base_url(&serve(), API_KEY);
+    let hosts = client.hosts(Some("Blog"), Some("db")).unwrap();
+    assert_eq!(hosts.len(), 2);
+    assert_eq!(
+        hosts[1],
+        Host {
+            id: "22CXRB3pZmv".to_string(),
+            name: "db02".to_string(),
+            roles: vec![("Blog".to_string(), "db".to_string()), ("Blog".to_string(), "app".to_string())],
+        }
+    );
+}
+
+#[test]
+fn test_fetch_role() {
+    let client = Client::with_base_url(&serve(), API_KEY);
+    let metric = Metric::Role("Blog".to_string(), "db".to_string(), "loadavg5".to_string());
+    assert_eq!(
+        fetch(&client, &metric, 100, 200),
+        Ok(vec![
+            Series {
+                name: "db01 loadavg5".to_string(),
+                points: vec![Point { time: 120, value: 1.5 }, Point { time: 180, value: 2.0 }],
+            },
+            Series { name: "db02 loadavg5".to_string(), points: vec![Point { time: 120, value: 0.5 }] },
+        ])
+    );
+}
+
+#[test]
+fn test_fetch_service_wildcard() {
+    let client = Client::with_base_url(&serve(), API_KEY);
+    let metric = Metric::Service("Blog".to_string(), "access_count.*".to_string());
+    let series = fetch(&client, &metric, 100, 200).unwrap();
+    assert_eq!(
+        series.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(),
+        vec!["access_count.2xx", "access_count.5xx"]
+    );
+}
+
+#[test]
+fn test_errors() {
+    let client = Client::with_base_url(&serve(), "wrong-key");
+    assert!(client.services().unwrap_err().contains("403"));
+    let client = Client::with_base_url(&serve(), API_KEY);
+    assert!(client.host_metric_names("unknown").unwrap_err().contains("404"));
+}
+
+#[test]
+fn test_path_segments() {
+    let client = Client::with_base_url(&serve(), API_KEY);
+    assert_eq!(client.service_metric_names("My Blog/v2"), Ok(vec!["latency".to_string()]));
+}